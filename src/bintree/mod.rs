@@ -1,3 +1,2 @@
-pub(crate) mod def;
 pub mod tree;
 pub(crate) mod treemap;
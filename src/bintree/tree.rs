@@ -1,5 +1,5 @@
-use super::{def::*, treemap::TreeMap};
-use crate::{align_down, buddy::def::PAGE_SIZE};
+use super::treemap::TreeMap;
+use crate::{align_down, is_align};
 
 #[derive(Debug)]
 pub enum TreeErr {
@@ -9,71 +9,78 @@ pub enum TreeErr {
 }
 
 // 完全二叉树
+// PGSZ 为叶节点(最小块)的大小，MAX_ORDER 为可管理的最大阶数
 #[repr(C)]
 #[derive(Debug)]
-pub struct BinTree {
-    pub level: usize,          // 树的高度
-    nodes: [usize; MAX_NODES], // 节点数组
-    pub bitmap: TreeMap,       // 位图
+pub struct BinTree<
+    const PGSZ: usize = { crate::def::PGSZ },
+    const MAX_ORDER: usize = { crate::def::MAX_ORDER },
+> {
+    pub level: usize,    // 树的高度
+    root: usize,         // 根节点地址，其余节点地址由此计算
+    pub bitmap: TreeMap, // 位图
 }
 
 #[allow(unused)]
-impl BinTree {
-    pub fn new() -> Self {
+impl<const PGSZ: usize, const MAX_ORDER: usize> BinTree<PGSZ, MAX_ORDER> {
+    pub const MAX_SIZE: usize = PGSZ << MAX_ORDER; // 可管理的最大内存
+    pub const MIN_SIZE: usize = PGSZ; // 可管理的最小内存
+    pub const MAX_LEAF: usize = 1 << MAX_ORDER; // 树最大叶节点数
+
+    pub const fn new() -> Self {
         Self {
-            nodes: [0; MAX_NODES],
+            root: 0,
             bitmap: TreeMap::new(),
             level: 0,
         }
     }
 
+    // 管理size大小的内存所需的位图字节数
+    pub const fn map_size(size: usize) -> usize {
+        let leaf_counts = size / Self::MIN_SIZE;
+        if leaf_counts == 0 {
+            0
+        } else {
+            TreeMap::bytes_for(leaf_counts.next_power_of_two() * 2 - 1)
+        }
+    }
+
     // 初始化完全二叉树
-    pub fn init(&mut self, root: usize, size: usize) -> Result<usize, TreeErr> {
-        let mut mem_size = align_down!(size, MIN_SIZE);
-        let mut leaf_counts = mem_size / MIN_SIZE;
-        let mut tmp_leaf = leaf_counts;
-        let mut tmp_size = mem_size;
+    // 位图由调用者提供，至少需要 map_size(size) 字节
+    // 不写入任何节点，只需按字节填充位图，位图每字节对应8个节点
+    pub fn init(&mut self, root: usize, size: usize, bitmap: TreeMap) -> Result<usize, TreeErr> {
+        let mem_size = align_down!(size, Self::MIN_SIZE);
+        let leaf_counts = mem_size / Self::MIN_SIZE;
 
         if leaf_counts == 0 {
             return Err(TreeErr::NotEnough);
+        } else if leaf_counts > Self::MAX_LEAF {
+            return Err(TreeErr::WrongSize);
         }
 
         // 向上找到最大节点数
-        while !tmp_leaf.is_power_of_two() {
-            tmp_leaf += 1;
-            tmp_size += PAGE_SIZE;
-        }
-
-        // 先将所有页的bit位设置为1(used)
-        self.bitmap.set_bit_all();
-
+        let tmp_leaf = leaf_counts.next_power_of_two();
         let node_counts = tmp_leaf * 2 - 1;
-        let mut cur_size = tmp_size;
-        let mut counts = 0;
 
-        // 将页地址放入二叉树中，每放入一个则设置其bit位为0(unused)
-        while counts < node_counts {
-            let mut current = root;
-
-            while current < (root + tmp_size) {
-                self.nodes[counts] = current;
-                self.bitmap.unset_bit(counts);
+        if bitmap.capacity() < node_counts {
+            return Err(TreeErr::NotEnough);
+        }
 
-                current += cur_size;
-                counts += 1;
-            }
+        // 节点地址不再保存，由根地址、高度和索引计算得到
+        // 因此只需按字节填充位图：树中节点为0(unused)，其余为1(used)
+        self.bitmap = bitmap;
+        let capacity = self.bitmap.capacity();
+        self.bitmap.fill(0, node_counts, false);
+        self.bitmap.fill(node_counts, capacity, true);
 
-            cur_size >>= 1;
-            self.level += 1;
-        }
+        self.root = root;
+        self.level = tmp_leaf.trailing_zeros() as usize + 1;
 
         // 将不可用的地址设为used
         if tmp_leaf > leaf_counts {
-            let level = self.get_level(PAGE_SIZE);
+            let level = self.get_level(Self::MIN_SIZE);
             let idx = self.get_index(level);
-            for i in (idx + leaf_counts)..(idx + tmp_leaf) {
-                self.bitmap.set_bit(i);
-            }
+            self.bitmap.fill(idx + leaf_counts, idx + tmp_leaf, true);
         }
 
         Ok(leaf_counts)
@@ -81,10 +88,10 @@ impl BinTree {
 
     // 根据size获取对应节点位于树的高度
     pub fn get_level(&self, size: usize) -> usize {
-        let mut index_size = align_down!(size, MIN_SIZE);
+        let mut index_size = align_down!(size, Self::MIN_SIZE);
         let mut level = self.level;
 
-        while index_size > MIN_SIZE {
+        while index_size > Self::MIN_SIZE {
             index_size >>= 1;
             level -= 1;
         }
@@ -97,9 +104,13 @@ impl BinTree {
         2usize.pow((level - 1) as u32) - 1
     }
 
-    // 根据索引获取对应节点的内容
+    // 根据索引获取对应节点的地址
+    // 节点所在高度的块大小为 MIN_SIZE << (树高 - 节点高度)
     pub fn get_value(&self, idx: usize) -> usize {
-        self.nodes[idx]
+        let level = (idx + 1).ilog2() as usize + 1;
+        let offset = idx - self.get_index(level);
+
+        self.root + offset * (Self::MIN_SIZE << (self.level - level))
     }
 
     // 进行适配搜索
@@ -107,7 +118,7 @@ impl BinTree {
     // 目前只能找到第一个适合(used or unused)的节点，如果能返回一个迭代器或者数组
     // 也就是所有适合的节点，将更方便
     pub fn find(&self, size: usize, is_used: bool) -> Result<usize, TreeErr> {
-        if size > MAX_SIZE {
+        if size > Self::MAX_SIZE {
             return Err(TreeErr::WrongSize);
         }
 
@@ -116,21 +127,8 @@ impl BinTree {
         let mut idx = self.get_index(level);
 
         while idx < (self.get_index(level + 1) - 1) {
-            if self.bitmap.is_empty(idx) != is_used {
-                let mut left_leaf = idx;
-
-                while self.find_left_child(left_leaf) <= self.max_node() {
-                    left_leaf = self.find_left_child(left_leaf);
-                }
-
-                let mut page_counts = size / MIN_SIZE;
-                let mut page = 0;
-
-                if is_used && self.can_free(left_leaf, page_counts)
-                    || !is_used && self.can_use(left_leaf, page_counts)
-                {
-                    break;
-                }
+            if self.fits(idx, size, is_used) {
+                break;
             }
 
             idx += 1;
@@ -143,8 +141,47 @@ impl BinTree {
         }
     }
 
+    // 与 find 相同，但只返回起始地址按align对齐的节点，
+    // 并从该高度的第offset个节点开始查找，到末尾后回到开头，用于随机选择满足条件的块
+    pub fn find_from(
+        &self,
+        size: usize,
+        is_used: bool,
+        align: usize,
+        offset: usize,
+    ) -> Result<usize, TreeErr> {
+        if size > Self::MAX_SIZE {
+            return Err(TreeErr::WrongSize);
+        }
+
+        let level = self.get_level(size);
+        let first = self.get_index(level);
+        let counts = self.get_index(level + 1) - first;
+
+        (0..counts)
+            .map(|i| first + (offset + i) % counts)
+            .find(|&idx| is_align!(self.get_value(idx), align) && self.fits(idx, size, is_used))
+            .ok_or(TreeErr::NotFound)
+    }
+
+    // 节点的状态为is_used，且其下size大小的连续页都可以使用(或释放)
+    fn fits(&self, idx: usize, size: usize, is_used: bool) -> bool {
+        if self.bitmap.is_empty(idx) == is_used {
+            return false;
+        }
+
+        let mut left_leaf = idx;
+        while self.find_left_child(left_leaf) <= self.max_node() {
+            left_leaf = self.find_left_child(left_leaf);
+        }
+
+        let page_counts = size / Self::MIN_SIZE;
+        is_used && self.can_free(left_leaf, page_counts)
+            || !is_used && self.can_use(left_leaf, page_counts)
+    }
+
     pub fn find_match(&self, size: usize, value: usize, is_used: bool) -> Result<usize, TreeErr> {
-        if size > MAX_SIZE {
+        if size > Self::MAX_SIZE {
             return Err(TreeErr::WrongSize);
         }
 
@@ -152,10 +189,10 @@ impl BinTree {
         // 接着遍历之后每个节点，待改进find，能够返回多个适合的节点
         let level = self.get_level(size);
         let max_idx = self.get_index(level + 1);
-        let mut idx = self.find(size, is_used).unwrap();
+        let mut idx = self.find(size, is_used)?;
 
         while idx < max_idx {
-            if self.get_value(idx) == value {
+            if self.get_value(idx) == value && self.fits(idx, size, is_used) {
                 return Ok(idx);
             }
             idx += 1;
@@ -242,12 +279,57 @@ impl BinTree {
         }
         true
     }
+
+    // 树的叶节点数
+    pub fn leaf_counts(&self) -> usize {
+        1 << (self.level - 1)
+    }
+
+    // 获取第page页对应的叶节点索引
+    pub fn leaf_index(&self, page: usize) -> usize {
+        self.get_index(self.level) + page
+    }
+
+    // 从第page页开始的counts页中，取出起始处最大的完整子树
+    // 返回子树根节点的索引及其包含的页数
+    pub fn range_block(&self, page: usize, counts: usize) -> (usize, usize) {
+        let mut order = core::cmp::min(page.trailing_zeros() as usize, self.level - 1);
+        while (1 << order) > counts {
+            order -= 1;
+        }
+
+        (
+            self.get_index(self.level - order) + (page >> order),
+            1 << order,
+        )
+    }
+
+    // 将从第page页开始的counts页设置为used
+    // 该范围不必是一个完整的子树，会被分解为若干个子树分别设置
+    pub fn use_range(&mut self, mut page: usize, mut counts: usize) {
+        while counts > 0 {
+            let (idx, pages) = self.range_block(page, counts);
+            self.use_mem(idx);
+            page += pages;
+            counts -= pages;
+        }
+    }
+
+    // 将从第page页开始的counts页设置为unused
+    pub fn unuse_range(&mut self, mut page: usize, mut counts: usize) {
+        while counts > 0 {
+            let (idx, pages) = self.range_block(page, counts);
+            self.unuse_mem(idx);
+            page += pages;
+            counts -= pages;
+        }
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::BinTree;
-    use crate::def::PGSZ;
+    use crate::{bintree::treemap::TreeMap, def::PGSZ};
     extern crate alloc;
     extern crate std;
     use std::println;
@@ -260,14 +342,20 @@ pub mod tests {
         }
     }
 
+    // 使用map作为测试树的位图
+    fn bitmap(map: &mut [u8]) -> TreeMap {
+        unsafe { TreeMap::from_raw(map.as_mut_ptr(), map.len()) }
+    }
+
     #[test]
     fn get_level_test() {
-        let mut tree1 = BinTree::new();
-        let mut tree2 = BinTree::new();
-        let mut tree3 = BinTree::new();
-        let _ = tree1.init(0x10000, PGSZ);
-        let _ = tree2.init(0x10000, PGSZ * 2);
-        let _ = tree3.init(0x10000, PGSZ * 3);
+        let (mut map1, mut map2, mut map3) = ([0u8; 64], [0u8; 64], [0u8; 64]);
+        let mut tree1: BinTree = BinTree::new();
+        let mut tree2: BinTree = BinTree::new();
+        let mut tree3: BinTree = BinTree::new();
+        let _ = tree1.init(0x10000, PGSZ, bitmap(&mut map1));
+        let _ = tree2.init(0x10000, PGSZ * 2, bitmap(&mut map2));
+        let _ = tree3.init(0x10000, PGSZ * 3, bitmap(&mut map3));
 
         for i in 0..tree1.level {
             assert_eq!(i + 1, tree1.get_level(PGSZ * (1 >> i)));
@@ -284,12 +372,13 @@ pub mod tests {
 
     #[test]
     fn get_index_test() {
-        let mut tree1 = BinTree::new();
-        let mut tree2 = BinTree::new();
-        let mut tree3 = BinTree::new();
-        let _ = tree1.init(0x10000, PGSZ);
-        let _ = tree2.init(0x10000, PGSZ * 2);
-        let _ = tree3.init(0x10000, PGSZ * 3);
+        let (mut map1, mut map2, mut map3) = ([0u8; 64], [0u8; 64], [0u8; 64]);
+        let mut tree1: BinTree = BinTree::new();
+        let mut tree2: BinTree = BinTree::new();
+        let mut tree3: BinTree = BinTree::new();
+        let _ = tree1.init(0x10000, PGSZ, bitmap(&mut map1));
+        let _ = tree2.init(0x10000, PGSZ * 2, bitmap(&mut map2));
+        let _ = tree3.init(0x10000, PGSZ * 3, bitmap(&mut map3));
 
         for i in 0..tree1.level {
             assert_eq!((2usize.pow(i as u32)) - 1, tree1.get_index(i + 1));
@@ -306,8 +395,9 @@ pub mod tests {
 
     #[test]
     fn find_test() {
-        let mut tree = BinTree::new();
-        let _ = tree.init(0x10000, PGSZ << 1);
+        let mut map = [0u8; 64];
+        let mut tree: BinTree = BinTree::new();
+        let _ = tree.init(0x10000, PGSZ << 1, bitmap(&mut map));
 
         assert!(tree.find(PGSZ << 1, false).is_ok());
         assert_eq!(0, tree.find(PGSZ << 1, false).unwrap());
@@ -320,15 +410,62 @@ pub mod tests {
         assert_eq!(1, tree.find(PGSZ, true).unwrap());
     }
 
+    #[test]
+    fn find_from_test() {
+        let mut map = [0u8; 64];
+        let mut tree: BinTree = BinTree::new();
+        let _ = tree.init(0x10000, PGSZ * 4, bitmap(&mut map));
+
+        // 从最后一个叶子开始，回到开头找到按两页对齐的节点
+        assert_eq!(6, tree.find_from(PGSZ, false, PGSZ, 3).unwrap());
+        assert_eq!(3, tree.find_from(PGSZ, false, PGSZ * 2, 3).unwrap());
+        tree.bitmap.set_bit(3);
+        assert_eq!(5, tree.find_from(PGSZ, false, PGSZ * 2, 3).unwrap());
+        tree.bitmap.set_bit(5);
+        assert!(tree.find_from(PGSZ, false, PGSZ * 2, 3).is_err());
+    }
+
+    #[test]
+    fn get_value_test() {
+        let mut map = [0u8; 64];
+        let mut tree: BinTree = BinTree::new();
+        let _ = tree.init(0x10000, PGSZ * 3, bitmap(&mut map));
+
+        assert_eq!(0x10000, tree.get_value(0));
+        assert_eq!(0x10000 + PGSZ * 2, tree.get_value(2));
+        for i in 0..4 {
+            assert_eq!(0x10000 + PGSZ * i, tree.get_value(3 + i));
+        }
+    }
+
+    #[test]
+    fn range_test() {
+        let mut map = [0u8; 64];
+        let mut tree: BinTree = BinTree::new();
+        let _ = tree.init(0x10000, PGSZ * 8, bitmap(&mut map));
+
+        // 第2~5页跨越了两个4页的子树，分解为两个2页的子树
+        assert_eq!((tree.get_index(3) + 1, 2), tree.range_block(2, 4));
+        tree.use_range(2, 4);
+        assert!(tree.can_use(tree.leaf_index(0), 2));
+        assert!(tree.can_free(tree.leaf_index(2), 4));
+        assert!(tree.can_use(tree.leaf_index(6), 2));
+
+        tree.unuse_range(2, 4);
+        assert!(tree.can_use(tree.leaf_index(0), 8));
+        assert_eq!(0, tree.find(PGSZ * 8, false).unwrap());
+    }
+
     #[test]
     fn init_test() {
         init_log(&PT, xxos_log::Level::INFO);
 
-        let mut tree = BinTree::new();
-        let mut bad_tree = BinTree::new();
+        let (mut map, mut bad_map) = ([0u8; 64], [0u8; 64]);
+        let mut tree: BinTree = BinTree::new();
+        let mut bad_tree: BinTree = BinTree::new();
 
-        let gen_success = tree.init(0x10000, PGSZ * 10);
-        let gen_error = bad_tree.init(0x10000, PGSZ / 2);
+        let gen_success = tree.init(0x10000, PGSZ * 10, bitmap(&mut map));
+        let gen_error = bad_tree.init(0x10000, PGSZ / 2, bitmap(&mut bad_map));
 
         assert!(gen_success.is_ok());
         assert!(gen_error.is_err());
@@ -1,11 +1,14 @@
-use core::mem::size_of;
-
-use super::def::MAX_NODES;
+use crate::{align_down, align_up};
+use core::slice;
 
 // 二叉树的位图
+// 位图保存在被管理的内存中，大小由树的节点数决定
 #[derive(Debug)]
 #[repr(C)]
-pub struct TreeMap([u8; MAX_NODES]);
+pub struct TreeMap {
+    map: *mut u8,
+    len: usize,
+}
 
 impl Default for TreeMap {
     fn default() -> Self {
@@ -15,44 +18,99 @@ impl Default for TreeMap {
 
 #[allow(unused)]
 impl TreeMap {
-    pub fn new() -> Self {
-        Self([0; MAX_NODES])
+    pub const fn new() -> Self {
+        Self {
+            map: core::ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    // 使用从map开始的len个字节作为位图
+    /// # Safety
+    pub unsafe fn from_raw(map: *mut u8, len: usize) -> Self {
+        Self { map, len }
+    }
+
+    // 保存nodes个bit位所需的字节数
+    pub const fn bytes_for(nodes: usize) -> usize {
+        nodes.div_ceil(u8::BITS as usize)
+    }
+
+    // 可保存的bit位数
+    pub fn capacity(&self) -> usize {
+        self.len * u8::BITS as usize
+    }
+
+    fn bytes(&self) -> &[u8] {
+        if self.map.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(self.map, self.len) }
+        }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        if self.map.is_null() {
+            &mut []
+        } else {
+            unsafe { slice::from_raw_parts_mut(self.map, self.len) }
+        }
     }
 
     // 获取对应bit位
     pub fn is_empty(&self, idx: usize) -> bool {
-        let byte_index = idx / size_of::<usize>();
-        let bit_index = idx % size_of::<usize>();
+        let byte_index = idx / u8::BITS as usize;
+        let bit_index = idx % u8::BITS as usize;
 
-        (self.0[byte_index] & (1 << bit_index)) == 0
+        (self.bytes()[byte_index] & (1 << bit_index)) == 0
     }
 
     // 设置对应bit位为1
     pub fn set_bit(&mut self, idx: usize) {
-        let byte_index = idx / size_of::<usize>();
-        let bit_index = idx % size_of::<usize>();
+        let byte_index = idx / u8::BITS as usize;
+        let bit_index = idx % u8::BITS as usize;
 
-        self.0[byte_index] |= 1 << bit_index;
+        self.bytes_mut()[byte_index] |= 1 << bit_index;
     }
 
     // 设置对应bit位为0
     pub fn unset_bit(&mut self, idx: usize) {
-        let byte_index = idx / size_of::<usize>();
-        let bit_index = idx % size_of::<usize>();
+        let byte_index = idx / u8::BITS as usize;
+        let bit_index = idx % u8::BITS as usize;
+
+        self.bytes_mut()[byte_index] &= !(1 << bit_index);
+    }
+
+    // 将[start, end)的bit位设置为used，首尾之间的整字节直接填充
+    pub fn fill(&mut self, start: usize, end: usize, used: bool) {
+        const BITS: usize = u8::BITS as usize;
+        let head = core::cmp::min(align_up!(start, BITS), end);
+        let tail = core::cmp::max(align_down!(end, BITS), head);
+        for idx in (start..head).chain(tail..end) {
+            self.put(idx, used);
+        }
+        let byte = if used { !0 } else { 0 };
+        self.bytes_mut()[head / BITS..tail / BITS].fill(byte);
+    }
 
-        self.0[byte_index] &= !(1 << bit_index);
+    fn put(&mut self, idx: usize, used: bool) {
+        if used {
+            self.set_bit(idx);
+        } else {
+            self.unset_bit(idx);
+        }
     }
 
     // 设置全部bit位为1
     pub fn set_bit_all(&mut self) {
-        for i in self.0.iter_mut() {
+        for i in self.bytes_mut().iter_mut() {
             *i = !0;
         }
     }
 
     // 设置全部bit位为0
     pub fn unset_bit_all(&mut self) {
-        for i in self.0.iter_mut() {
+        for i in self.bytes_mut().iter_mut() {
             *i = 0;
         }
     }
@@ -62,12 +120,14 @@ impl TreeMap {
 pub mod tests {
     extern crate std;
     use super::TreeMap;
-    use crate::bintree::def::MAX_NODES;
     use std::panic;
 
+    const MAX_NODES: usize = (1 << 10) - 1;
+
     #[test]
     fn map_test() {
-        let mut bitmap = TreeMap::new();
+        let mut map = [0u8; TreeMap::bytes_for(MAX_NODES)];
+        let mut bitmap = unsafe { TreeMap::from_raw(map.as_mut_ptr(), map.len()) };
 
         for i in 0..MAX_NODES {
             if bitmap.is_empty(i) {
@@ -89,5 +149,14 @@ pub mod tests {
         for i in 0..MAX_NODES {
             assert!(bitmap.is_empty(i));
         }
+
+        // 范围填充与逐位设置一致
+        for (start, end) in [(3, 5), (3, 70), (8, 64), (0, 1), (10, 10)] {
+            bitmap.fill(0, MAX_NODES, false);
+            bitmap.fill(start, end, true);
+            for i in 0..MAX_NODES {
+                assert_eq!((start..end).contains(&i), !bitmap.is_empty(i));
+            }
+        }
     }
 }
@@ -1,10 +1,16 @@
-use xxos_log::{error, info};
-
-use super::def::{MemPtr, MAX_PAGES, PAGE_SIZE};
+use super::def::MemPtr;
+#[cfg(feature = "randomize")]
+use crate::rand::Rng;
 use crate::{
     align_down, align_up,
-    bintree::tree::{BinTree, TreeErr},
+    bintree::{
+        tree::{BinTree, TreeErr},
+        treemap::TreeMap,
+    },
     is_align,
+    linklist::link::Linkedlist,
+    trace::Event,
+    trace_event,
 };
 use core::{alloc::Layout, mem::size_of, ptr::null_mut};
 
@@ -15,6 +21,8 @@ pub enum BuddyErr {
     NotFound,
     WrongSize,
     WrongAddr,
+    OutOfZone,
+    DoubleFree,
 }
 
 impl From<TreeErr> for BuddyErr {
@@ -30,12 +38,13 @@ impl From<TreeErr> for BuddyErr {
 /// 页内存分配器
 /// 用来分配连续的页内存，使用完全二叉树来管理
 /// 因此管理的页数为2的幂
+/// PGSZ 为页大小，MAX_ORDER 为最大阶数，最多管理 1 << MAX_ORDER 页
 /// Example:
 /// ```
 /// const PAGE_COUNTS: usize = 16;
 ///
 /// let test_mem: [usize; PAGE_SIZE * (PAGE_COUNTS + 1) / 8] = [0; (PAGE_SIZE * (PAGE_COUNTS + 1) / 8)];
-/// let mut buddy = BuddyAllocator::new();
+/// let mut buddy: BuddyAllocator = BuddyAllocator::new();
 /// unsafe { buddy.init(bottom, top) };
 /// let bottom = &test_mem[0] as *const _ as usize;
 /// let top = &test_mem[PAGE_SIZE * (PAGE_COUNTS + 1) / 8 - 1] as *const _ as usize;
@@ -43,69 +52,113 @@ impl From<TreeErr> for BuddyErr {
 /// let mut addr2 = unsafe { buddy.allocate(Layout::from_size_align(PAGE_SIZE << 1, PAGE_SIZE).unwrap()) };
 /// let _ = buddy.deallocate(addr1.unwrap(), PAGE_SIZE);
 /// ```
+///
+/// 大页(huge page)是阶数为order、按自身大小自然对齐的连续页，
+/// 例如 PGSZ 为4KiB时，order为9即2MiB大页。可以通过 reserve_huge
+/// 预留一部分大页，预留的大页不会被普通分配拆散
 #[derive(Debug)]
-pub struct BuddyAllocator {
-    zone: *mut BinTree, // 二叉树
-    page_counts: usize, // 剩余空闲页
+pub struct BuddyAllocator<
+    const PGSZ: usize = { crate::def::PGSZ },
+    const MAX_ORDER: usize = { crate::def::MAX_ORDER },
+> {
+    zone: *mut BinTree<PGSZ, MAX_ORDER>, // 二叉树
+    page_counts: usize,                  // 剩余空闲页
+    total_pages: usize,                  // 被管理的总页数
+    huge_pool: Linkedlist,               // 预留的大页
+    huge_order: usize,                   // 预留大页的阶数
+    huge_reserved: usize,                // 需要预留的大页数
+    huge_free: usize,                    // 预留池中空闲的大页数
+    huge_map: TreeMap,                   // 预留池中大页的每一页，每页一位
+    #[cfg(feature = "randomize")]
+    rng: Rng, // 随机选择满足分配的空闲块
 }
 
+unsafe impl<const PGSZ: usize, const MAX_ORDER: usize> Send for BuddyAllocator<PGSZ, MAX_ORDER> {}
+
 #[allow(unused)]
-impl BuddyAllocator {
+impl<const PGSZ: usize, const MAX_ORDER: usize> BuddyAllocator<PGSZ, MAX_ORDER> {
+    pub const PAGE_SIZE: usize = PGSZ;
+    pub const MAX_PAGES: usize = BinTree::<PGSZ, MAX_ORDER>::MAX_LEAF;
+
     pub const fn new() -> Self {
         Self {
             zone: null_mut(),
             page_counts: 0,
+            total_pages: 0,
+            huge_pool: Linkedlist::new(),
+            huge_order: 0,
+            huge_reserved: 0,
+            huge_free: 0,
+            huge_map: TreeMap::new(),
+            #[cfg(feature = "randomize")]
+            rng: Rng::new(),
         }
     }
 
+    // 设置选择空闲块的随机数种子
+    #[cfg(feature = "randomize")]
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng.seed(seed);
+    }
+
     // 初始化zone
     // 需要起始地址和总内存大小
     /// # Safety
     pub unsafe fn init(&mut self, bottom: MemPtr, top: MemPtr) {
-        let start = align_up!(bottom, PAGE_SIZE);
-        let end = align_down!(top, PAGE_SIZE);
-        let mut zone = start as *mut BinTree;
-        let mut page_counts = (end - start) / PAGE_SIZE;
+        let start = align_up!(bottom, Self::PAGE_SIZE);
+        let end = align_down!(top, Self::PAGE_SIZE);
+        let mut page_counts = (end - start) / Self::PAGE_SIZE;
 
-        info!(
-            "BuddyAllocator::init(bottom: {:#x}, top: {:#x}) start",
-            bottom, top
-        );
+        // 未设置种子时由地址生成
+        #[cfg(feature = "randomize")]
+        self.rng.seed((bottom ^ top.rotate_left(17)) as u64);
 
-        if page_counts > MAX_PAGES {
+        // 二叉树及其位图、预留池的位图保存在待管理内存的前几页
+        let map_size = BinTree::<PGSZ, MAX_ORDER>::map_size(Self::PAGE_SIZE * page_counts);
+        let huge_size = TreeMap::bytes_for(page_counts);
+        let meta_size = size_of::<BinTree<PGSZ, MAX_ORDER>>() + map_size + huge_size;
+        let used = align_up!(meta_size, Self::PAGE_SIZE) / Self::PAGE_SIZE;
+
+        if page_counts > Self::MAX_PAGES {
             panic!("size is too big.");
-        } else if page_counts < size_of::<BinTree>() / PAGE_SIZE {
-            panic!(
-                "size is too small, at least {} pages.",
-                size_of::<BinTree>() / PAGE_SIZE
-            );
+        } else if page_counts <= used {
+            panic!("size is too small, at least {} pages.", used + 1);
         }
 
-        self.zone = start as *mut BinTree;
+        self.zone = start as *mut BinTree<PGSZ, MAX_ORDER>;
         self.page_counts = page_counts;
 
-        info!(
-            "mem_start: {:#x} mem_end: {:#x} pages: {}",
-            start,
-            start + page_counts * PAGE_SIZE,
-            page_counts
+        let bitmap = TreeMap::from_raw(
+            (start + size_of::<BinTree<PGSZ, MAX_ORDER>>()) as *mut u8,
+            map_size,
         );
+        self.zone.write(BinTree::new());
+        self.huge_map = TreeMap::from_raw(
+            (start + size_of::<BinTree<PGSZ, MAX_ORDER>>() + map_size) as *mut u8,
+            huge_size,
+        );
+        self.huge_map.fill(0, page_counts, false);
 
-        match (*self.zone).init(self.zone as usize, PAGE_SIZE * self.page_counts) {
+        match (*self.zone).init(
+            self.zone as usize,
+            Self::PAGE_SIZE * self.page_counts,
+            bitmap,
+        ) {
             Ok(counts) => {
                 // 直接使用待管理内存的前几页保存该分配器，因此设置为used
-                let used = align_up!(size_of::<BinTree>(), PAGE_SIZE) / PAGE_SIZE;
                 let index = (*self.zone).get_index((*self.zone).level);
 
                 for i in 0..used {
                     (*self.zone).use_page(index + i);
                 }
 
+                self.total_pages = counts;
                 self.page_counts = counts - used;
-                info!(
-                    "buddy initialize successfuly, have {} free pages.",
-                    self.page_counts
-                );
+                trace_event!(Event::BuddyInit {
+                    start,
+                    end,
+                    free_pages: self.page_counts
+                });
             }
             Err(_) => {
                 panic!("buddy initialize failure");
@@ -116,61 +169,45 @@ impl BuddyAllocator {
     // 分配内存，需要提供待分配内存大小
     /// # Safety
     pub unsafe fn allocate(&mut self, layout: Layout) -> Result<MemPtr, BuddyErr> {
-        info!(
-            "BuddyAllocator::allocate({:#x}, align_size: {:#x}) start",
-            layout.size(),
-            layout.align()
-        );
-
         let size = layout.size();
         let align_size = layout.align();
-        let mem_size = align_up!(size, PAGE_SIZE);
+        let mem_size = align_up!(size, Self::PAGE_SIZE);
 
         if self.page_counts == 0 {
             Err(BuddyErr::None)
         } else {
-            let mut addr = 0;
-            let counts = size / PAGE_SIZE;
+            let counts = size / Self::PAGE_SIZE;
 
             if counts > self.page_counts {
                 return Err(BuddyErr::NotEnough);
             }
 
-            // 剩余页面足够时，找到对应的unused节点并设置为used
+            // 剩余页面足够时，找到与layout对齐的unused节点并设置为used
             // 剩余页面减少
-            let mut idx = (*self.zone).find(mem_size, false)?;
-            let max_idx = (*self.zone).get_index((*self.zone).get_level(size));
-
-            // 找到与layout对齐的地址
-            addr = (*self.zone).get_value(idx);
-            while idx < max_idx && !is_align!(addr, align_size) {
-                idx += 1;
-                addr = (*self.zone).get_value(idx);
-            }
-
-            if idx != max_idx {
-                // 找到子树的最左节点
-                let mut left_leaf = idx;
-                let max_leaf = (*self.zone).max_node();
-                while (*self.zone).find_left_child(left_leaf) <= max_leaf {
-                    left_leaf = (*self.zone).find_left_child(left_leaf);
-                }
-
-                // 检查连续的页是否可用
-                if (*self.zone).can_use(left_leaf, counts) {
+            #[cfg(not(feature = "randomize"))]
+            let offset = 0;
+            #[cfg(feature = "randomize")]
+            let offset = self.rng.next_u64() as usize;
+            match (*self.zone).find_from(mem_size, false, align_size, offset) {
+                Ok(idx) => {
+                    let addr = (*self.zone).get_value(idx);
                     (*self.zone).use_mem(idx);
                     self.page_counts -= counts;
 
-                    info!("allocate {} pages successfuly.", counts);
+                    trace_event!(Event::PageAlloc {
+                        addr,
+                        pages: counts
+                    });
 
                     Ok(addr)
-                } else {
-                    error!("memory have already to used.");
-                    Err(BuddyErr::NotFound)
                 }
-            } else {
-                error!("can't find fit size pages.");
-                Err(BuddyErr::NotFound)
+                Err(err) => {
+                    trace_event!(Event::PageExhausted {
+                        size,
+                        align: align_size
+                    });
+                    Err(err.into())
+                }
             }
         }
     }
@@ -178,21 +215,36 @@ impl BuddyAllocator {
     // 释放内存，需要提供起始地址和内存大小
     /// # Safety
     pub unsafe fn deallocate(&mut self, addr: MemPtr, size: usize) -> Result<usize, BuddyErr> {
-        info!(
-            "BuddyAllocator::deallocate(addr: {:#x}, size: {:#x}) start",
-            addr, size
-        );
-        let counts = size / PAGE_SIZE;
+        let counts = size / Self::PAGE_SIZE;
 
         // 地址和大小需要对齐
-        if is_align!(addr, PAGE_SIZE) {
-            if is_align!(size, PAGE_SIZE) {
+        if is_align!(addr, Self::PAGE_SIZE) {
+            if is_align!(size, Self::PAGE_SIZE) {
                 let mut idx = 0;
 
+                // 地址需要在被管理的范围内，且对应的页都正在被使用
+                let start = self.zone as usize;
+                let end = start + self.total_pages * Self::PAGE_SIZE;
+                if addr < start || addr.checked_add(size).is_none_or(|last| last > end) {
+                    return Err(BuddyErr::OutOfZone);
+                }
+                // 预留池中的大页在二叉树中仍是used，但已经空闲
+                let zone = &*self.zone;
+                let page = (addr - start) / Self::PAGE_SIZE;
+                if !zone.can_free(zone.leaf_index(page), counts) || self.in_huge_pool(page, counts)
+                {
+                    return Err(BuddyErr::DoubleFree);
+                }
+
                 // 找到对应节点并设置其为unused
                 let index = (*self.zone).find_match(size, addr, true)?;
                 (*self.zone).unuse_mem(index);
+                self.page_counts += counts;
                 idx = index;
+                trace_event!(Event::PageFree {
+                    addr,
+                    pages: counts
+                });
 
                 Ok(idx)
             } else {
@@ -202,15 +254,274 @@ impl BuddyAllocator {
             Err(BuddyErr::WrongSize)
         }
     }
+
+    // 原地调整已分配内存的大小，新旧大小都需要是2的幂个页
+    // 增大时要求地址按新大小对齐且相邻的伙伴空闲，减小时释放后半部分的伙伴
+    /// # Safety
+    pub unsafe fn resize(
+        &mut self,
+        addr: MemPtr,
+        old_size: usize,
+        new_size: usize,
+    ) -> Result<(), BuddyErr> {
+        trace_event!(Event::PageResize {
+            addr,
+            old_pages: old_size / Self::PAGE_SIZE,
+            new_pages: new_size / Self::PAGE_SIZE
+        });
+
+        if self.zone.is_null() {
+            return Err(BuddyErr::None);
+        } else if !is_align!(addr, Self::PAGE_SIZE) || addr < self.zone as usize {
+            return Err(BuddyErr::WrongAddr);
+        }
+
+        let old_counts = old_size / Self::PAGE_SIZE;
+        let new_counts = new_size / Self::PAGE_SIZE;
+        if !is_align!(old_size, Self::PAGE_SIZE)
+            || !is_align!(new_size, Self::PAGE_SIZE)
+            || !old_counts.is_power_of_two()
+            || !new_counts.is_power_of_two()
+        {
+            return Err(BuddyErr::WrongSize);
+        }
+
+        let zone = &mut *self.zone;
+        let page = (addr - self.zone as usize) / Self::PAGE_SIZE;
+        if page + core::cmp::max(old_counts, new_counts) > zone.leaf_counts() {
+            return Err(BuddyErr::WrongSize);
+        } else if !zone.can_free(zone.leaf_index(page), old_counts)
+            || self.in_huge_pool(page, old_counts)
+        {
+            return Err(BuddyErr::NotFound);
+        }
+
+        // 地址需要是新旧大小对应节点的起始地址
+        let (idx, counts) = zone.range_block(page, new_counts);
+        let (old_idx, old) = zone.range_block(page, old_counts);
+        if counts != new_counts || old != old_counts {
+            return Err(BuddyErr::WrongAddr);
+        }
+
+        if new_counts > old_counts {
+            let grow = new_counts - old_counts;
+            if grow > self.page_counts || !zone.can_use(zone.leaf_index(page + old_counts), grow) {
+                return Err(BuddyErr::NotEnough);
+            }
+            zone.use_mem(idx);
+            self.page_counts -= grow;
+        } else if new_counts < old_counts {
+            // 释放后半部分，并清除新旧节点之间的祖先节点
+            zone.unuse_range(page + new_counts, old_counts - new_counts);
+            let mut parent = idx;
+            while parent != old_idx {
+                parent = zone.find_parent(parent);
+                zone.unuse_page(parent);
+            }
+            self.page_counts += old_counts - new_counts;
+        }
+
+        Ok(())
+    }
+
+    // 被管理内存的起始地址
+    pub fn zone_start(&self) -> MemPtr {
+        self.zone as MemPtr
+    }
+
+    // 被管理的总页数
+    pub fn total_pages(&self) -> usize {
+        self.total_pages
+    }
+
+    // 剩余空闲页数
+    pub fn free_pages(&self) -> usize {
+        self.page_counts
+    }
+
+    // 阶数为order的大页大小
+    pub const fn huge_size(order: usize) -> usize {
+        PGSZ << order
+    }
+
+    // 所有空闲且自然对齐的大页的起始页号
+    fn free_huge_pages(&self, order: usize) -> impl Iterator<Item = usize> + '_ {
+        let zone = unsafe { &*self.zone };
+        let root = self.zone as usize;
+        let counts = 1 << order;
+        let first = (align_up!(root, Self::huge_size(order)) - root) / Self::PAGE_SIZE;
+
+        (first..zone.leaf_counts())
+            .step_by(counts)
+            .take_while(move |page| page + counts <= zone.leaf_counts())
+            .filter(move |&page| zone.can_use(zone.leaf_index(page), counts))
+    }
+
+    // 从page开始的counts页中是否有页在预留池中
+    fn in_huge_pool(&self, page: usize, counts: usize) -> bool {
+        (page..page + counts).any(|page| !self.huge_map.is_empty(page))
+    }
+
+    // 大页放入预留池
+    unsafe fn push_huge(&mut self, addr: MemPtr) {
+        let page = (addr - self.zone as usize) / Self::PAGE_SIZE;
+        self.huge_map
+            .fill(page, page + (1 << self.huge_order), true);
+        self.huge_pool.push(addr);
+        self.huge_free += 1;
+    }
+
+    // 从预留池取出一个大页
+    unsafe fn pop_huge(&mut self) -> Option<MemPtr> {
+        let addr = self.huge_pool.pop::<u8>()? as MemPtr;
+        let page = (addr - self.zone as usize) / Self::PAGE_SIZE;
+        self.huge_map
+            .fill(page, page + (1 << self.huge_order), false);
+        self.huge_free -= 1;
+        Some(addr)
+    }
+
+    // 当前可以分配的大页数，包括预留池中的大页
+    pub fn huge_pages_available(&self, order: usize) -> usize {
+        if self.zone.is_null() || order > MAX_ORDER {
+            return 0;
+        }
+
+        let free = self.free_huge_pages(order).count();
+        if order == self.huge_order {
+            free + self.huge_free
+        } else {
+            free
+        }
+    }
+
+    // 直接从二叉树中分配一个大页，不经过预留池
+    unsafe fn allocate_huge_from_zone(&mut self, order: usize) -> Result<MemPtr, BuddyErr> {
+        if self.zone.is_null() {
+            return Err(BuddyErr::None);
+        } else if order > MAX_ORDER {
+            return Err(BuddyErr::WrongSize);
+        }
+
+        let counts = 1 << order;
+        if counts > self.page_counts {
+            return Err(BuddyErr::NotEnough);
+        }
+
+        let page = self
+            .free_huge_pages(order)
+            .next()
+            .ok_or(BuddyErr::NotFound)?;
+        (*self.zone).use_range(page, counts);
+        self.page_counts -= counts;
+
+        Ok(self.zone as usize + page * Self::PAGE_SIZE)
+    }
+
+    // 分配一个阶数为order的大页，地址按大页大小对齐
+    // 预留池中有对应阶数的大页时优先使用
+    /// # Safety
+    pub unsafe fn allocate_huge(&mut self, order: usize) -> Result<MemPtr, BuddyErr> {
+        if order == self.huge_order {
+            if let Some(addr) = self.pop_huge() {
+                trace_event!(Event::HugeAlloc { addr, order });
+                return Ok(addr);
+            }
+        }
+
+        let addr = self.allocate_huge_from_zone(order)?;
+        trace_event!(Event::HugeAlloc { addr, order });
+        Ok(addr)
+    }
+
+    // 释放大页，预留池未满时放回预留池
+    /// # Safety
+    pub unsafe fn deallocate_huge(&mut self, addr: MemPtr, order: usize) -> Result<(), BuddyErr> {
+        trace_event!(Event::HugeFree { addr, order });
+
+        let root = self.zone as usize;
+        let counts = 1 << order;
+
+        if order > MAX_ORDER {
+            return Err(BuddyErr::WrongSize);
+        } else if !is_align!(addr, Self::huge_size(order)) || addr < root {
+            return Err(BuddyErr::WrongAddr);
+        }
+
+        let page = (addr - root) / Self::PAGE_SIZE;
+        if page + counts > (*self.zone).leaf_counts()
+            || !(*self.zone).can_free((*self.zone).leaf_index(page), counts)
+        {
+            return Err(BuddyErr::WrongAddr);
+        }
+
+        // 预留池中的大页在二叉树中仍是used，由预留池的位图检查是否已经放回
+        if self.in_huge_pool(page, counts) {
+            return Err(BuddyErr::DoubleFree);
+        }
+
+        if order == self.huge_order && self.huge_free < self.huge_reserved {
+            self.push_huge(addr);
+        } else {
+            (*self.zone).unuse_range(page, counts);
+            self.page_counts += counts;
+        }
+
+        Ok(())
+    }
+
+    // 预留counts个阶数为order的大页，返回预留池中的大页数
+    // 预留的大页只能通过 allocate_huge 分配，counts为0时释放整个预留池
+    // 大页不足时归还本次预留的大页，阶数不变时恢复原来的预留数，阶数改变时原来的预留池已归还，不再预留
+    /// # Safety
+    pub unsafe fn reserve_huge(&mut self, order: usize, counts: usize) -> Result<usize, BuddyErr> {
+        trace_event!(Event::HugeReserve { order, counts });
+
+        // 阶数改变或预留数减少时，先归还多余的大页
+        while self.huge_free > 0 && (order != self.huge_order || self.huge_free > counts) {
+            let addr = self.pop_huge().expect("huge pool broken");
+            self.release_huge(addr);
+        }
+
+        let old_reserved = if order == self.huge_order {
+            self.huge_reserved
+        } else {
+            0
+        };
+        let old_free = self.huge_free;
+        self.huge_order = order;
+        self.huge_reserved = counts;
+
+        while self.huge_free < counts {
+            if let Err(err) = self
+                .allocate_huge_from_zone(order)
+                .map(|addr| self.push_huge(addr))
+            {
+                while self.huge_free > old_free {
+                    let addr = self.pop_huge().expect("huge pool broken");
+                    self.release_huge(addr);
+                }
+                self.huge_reserved = old_reserved;
+                return Err(err);
+            }
+        }
+
+        Ok(self.huge_free)
+    }
+
+    // 将预留池取出的大页归还给二叉树
+    unsafe fn release_huge(&mut self, addr: MemPtr) {
+        let page = (addr - self.zone as usize) / Self::PAGE_SIZE;
+        (*self.zone).unuse_range(page, 1 << self.huge_order);
+        self.page_counts += 1 << self.huge_order;
+    }
 }
 
 #[cfg(test)]
 #[allow(unused_imports)]
 pub mod buddy_tests {
     extern crate std;
-    use super::BuddyAllocator;
-    use crate::bintree::def::MIN_SIZE;
-    use crate::buddy::def::PAGE_SIZE;
+    use super::{BuddyAllocator, BuddyErr};
     use crate::def::PGSZ;
     use crate::{align_up, is_align};
     use core::alloc::Layout;
@@ -224,12 +535,19 @@ pub mod buddy_tests {
         }
     }
 
+    const PAGE_SIZE: usize = PGSZ;
+
     #[test]
     fn buddy_test() {
         init_log(&PT, xxos_log::Level::INFO);
 
         const PAGE_COUNTS: usize = (1 << 8) - 5;
-        let test_mem: [usize; PAGE_SIZE * PAGE_COUNTS / 8] = [0; (PAGE_SIZE * PAGE_COUNTS / 8)];
+        // 按两页对齐，使得块的对齐与栈地址无关
+        #[repr(C, align(8192))]
+        struct TestMem([usize; PAGE_SIZE * PAGE_COUNTS / 8]);
+
+        let test_mem = TestMem([0; (PAGE_SIZE * PAGE_COUNTS / 8)]);
+        let test_mem = &test_mem.0;
         info!(
             "test_mem_size: {:#x} Bytes, {} pages.",
             test_mem.len() * 8,
@@ -239,78 +557,267 @@ pub mod buddy_tests {
         let bottom = &test_mem[0] as *const _ as usize;
         let top = &test_mem[PAGE_SIZE * PAGE_COUNTS / 8 - 1] as *const _ as usize;
 
-        let mut buddy = BuddyAllocator::new();
+        let mut buddy: BuddyAllocator = BuddyAllocator::new();
         unsafe { buddy.init(bottom, top) };
+        let free = buddy.free_pages();
+
+        assert_eq!(align_up!(bottom, PAGE_SIZE), buddy.zone as usize);
+        let page = |addr: usize| (addr - bottom) / PAGE_SIZE;
+
+        let addr1 =
+            unsafe { buddy.allocate(Layout::from_size_align(PAGE_SIZE, PAGE_SIZE << 1).unwrap()) }
+                .unwrap();
+        info!("allocate addr1: {:#x}", addr1);
+        assert!(is_align!(addr1, PAGE_SIZE << 1));
+        // 第一页保存分配器本身，第一个按两页对齐的空闲页为第3页
+        #[cfg(not(feature = "randomize"))]
+        assert_eq!(bottom + 2 * PAGE_SIZE, addr1);
+
+        let addr2 =
+            unsafe { buddy.allocate(Layout::from_size_align(PAGE_SIZE << 1, PAGE_SIZE).unwrap()) }
+                .unwrap();
+        info!("allocate addr2: {:#x}", addr2);
+        assert!(is_align!(addr2, PAGE_SIZE << 1));
+        #[cfg(not(feature = "randomize"))]
+        assert_eq!(bottom + 4 * PAGE_SIZE, addr2);
 
-        assert_eq!(align_up!(bottom, MIN_SIZE), buddy.zone as usize);
-
-        let mut addr1 =
-            unsafe { buddy.allocate(Layout::from_size_align(PAGE_SIZE, PAGE_SIZE << 1).unwrap()) };
-        match addr1 {
-            Ok(addr) => {
-                info!("allocate addr1: {:#x}", addr);
-                assert_eq!(
-                     align_up!(bottom + 144 * PAGE_SIZE, PAGE_SIZE),
-                     addr,
-                     "\nThis result has related to the root address, example my root address is {:#x} so my first allocate aligned {:#x} address is page no.145(root + 144 * PGSZ = {:#x})",
-                     buddy.zone as usize,PAGE_SIZE<<1,align_up!(bottom+144*PAGE_SIZE,PAGE_SIZE)
-                 );
-            }
-            Err(_) => {
-                panic!("")
-            }
-        }
+        let addr3 =
+            unsafe { buddy.allocate(Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap()) }
+                .unwrap();
+        info!("allocate addr3: {:#x}", addr3);
+        #[cfg(not(feature = "randomize"))]
+        assert_eq!(bottom + PAGE_SIZE, addr3);
+
+        // 第一页保存分配器本身，分配的页互不重叠
+        let mut pages = [page(addr1), page(addr2), page(addr2) + 1, page(addr3)];
+        pages.sort();
+        assert!(pages[0] > 0);
+        assert!(pages.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(free - 4, buddy.free_pages());
+
+        // 释放返回节点的编号，叶子按页的顺序编号，两页的块为叶子的父节点
+        let leaf = |page: usize| unsafe { (*buddy.zone).leaf_index(page) };
+        let (leaf1, leaf2) = (leaf(page(addr1)), leaf(page(addr2)));
+        let free1 = unsafe { buddy.deallocate(addr1, PAGE_SIZE) }.unwrap();
+        assert_eq!(leaf1, free1);
+        let free2 = unsafe { buddy.deallocate(addr2, PAGE_SIZE << 1) }.unwrap();
+        assert_eq!((leaf2 - 1) / 2, free2);
+        #[cfg(not(feature = "randomize"))]
+        assert_eq!((257, 129), (free1, free2));
+        assert_eq!(free - 1, buddy.free_pages());
+
+        // 释放后的页可以再次分配
+        let addr1 =
+            unsafe { buddy.allocate(Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap()) }
+                .unwrap();
+        info!("allocate addr: {:#x}", addr1);
+        assert_ne!(addr3, addr1);
+        #[cfg(not(feature = "randomize"))]
+        assert_eq!(bottom + 2 * PAGE_SIZE, addr1);
+
+        let addr2 =
+            unsafe { buddy.allocate(Layout::from_size_align(PAGE_SIZE << 1, PAGE_SIZE).unwrap()) }
+                .unwrap();
+        info!("allocate addr: {:#x}", addr2);
+        assert!(is_align!(addr2, PAGE_SIZE << 1));
+        assert!(addr1 < addr2 || addr1 >= addr2 + (PAGE_SIZE << 1));
+        #[cfg(not(feature = "randomize"))]
+        assert_eq!(bottom + 4 * PAGE_SIZE, addr2);
+        assert_eq!(free - 4, buddy.free_pages());
+    }
 
-        let mut addr2 =
-            unsafe { buddy.allocate(Layout::from_size_align(PAGE_SIZE << 1, PAGE_SIZE).unwrap()) };
-        match addr2 {
-            Ok(addr) => {
-                info!("allocate addr2: {:#x}", addr);
-                assert_eq!(align_up!(bottom + 146 * PAGE_SIZE, PAGE_SIZE), addr);
-            }
-            Err(_) => {
-                panic!("");
-            }
-        }
+    #[test]
+    fn buddy_granule_test() {
+        // 以1KiB为粒度，最多管理256个块
+        const GRANULE: usize = 1024;
+        type SmallBuddy = BuddyAllocator<GRANULE, 8>;
 
-        let addr3 =
-            unsafe { buddy.allocate(Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap()) };
-        match addr3 {
-            Ok(addr) => {
-                info!("allocate addr3: {:#x}", addr);
-                assert_eq!(align_up!(bottom + 145 * PAGE_SIZE, PAGE_SIZE), addr,);
-            }
-            Err(_) => {
-                panic!("");
-            }
-        }
+        #[repr(C, align(1024))]
+        struct TestMem([usize; GRANULE * 64 / 8]);
 
-        let free1 = unsafe { buddy.deallocate(addr1.unwrap(), PAGE_SIZE) }.unwrap();
-        assert_eq!(399, free1);
-        let free2 = unsafe { buddy.deallocate(addr2.unwrap(), PAGE_SIZE << 1) }.unwrap();
-        assert_eq!(200, free2);
+        let test_mem = TestMem([0; GRANULE * 64 / 8]);
+        let bottom = &test_mem.0[0] as *const _ as usize;
+        let top = bottom + GRANULE * 64;
 
-        addr1 = unsafe { buddy.allocate(Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap()) };
-        match addr1 {
-            Ok(addr) => {
-                info!("allocate addr: {:#x}", addr);
-                assert_eq!(align_up!(bottom + 144 * PAGE_SIZE, PAGE_SIZE), addr);
-            }
-            Err(_) => {
-                panic!("");
-            }
-        }
+        let mut buddy = SmallBuddy::new();
+        unsafe { buddy.init(bottom, top) };
+        let free = buddy.free_pages();
+
+        // 块按粒度对齐，不与保存分配器本身的第一个块重叠
+        let addr1 =
+            unsafe { buddy.allocate(Layout::from_size_align(GRANULE, GRANULE).unwrap()) }.unwrap();
+        assert!(addr1 > bottom);
+        assert!(is_align!(addr1, GRANULE));
+
+        let addr2 =
+            unsafe { buddy.allocate(Layout::from_size_align(GRANULE * 4, GRANULE).unwrap()) }
+                .unwrap();
+        assert!(is_align!(addr2 - bottom, GRANULE * 4));
+        assert!(addr1 < addr2 || addr1 >= addr2 + GRANULE * 4);
+        assert_eq!(free - 5, buddy.free_pages());
+
+        assert!(unsafe { buddy.deallocate(addr1, GRANULE) }.is_ok());
+        assert!(unsafe { buddy.deallocate(addr2, GRANULE * 4) }.is_ok());
+        assert_eq!(free, buddy.free_pages());
+    }
 
-        addr2 =
-            unsafe { buddy.allocate(Layout::from_size_align(PAGE_SIZE << 1, PAGE_SIZE).unwrap()) };
-        match addr2 {
-            Ok(addr) => {
-                info!("allocate addr: {:#x}", addr);
-                assert_eq!(align_up!(bottom + 146 * PAGE_SIZE, PAGE_SIZE), addr);
-            }
-            Err(_) => {
-                panic!("");
-            }
+    #[test]
+    fn huge_page_test() {
+        const PAGE_COUNTS: usize = 64;
+        const HUGE_ORDER: usize = 2;
+        const HUGE_SIZE: usize = PAGE_SIZE << HUGE_ORDER;
+
+        #[repr(C, align(16384))]
+        struct TestMem([usize; PAGE_SIZE * PAGE_COUNTS / 8]);
+
+        // 管理的内存从第二页开始，二叉树中的块不是按大页自然对齐的
+        let test_mem = TestMem([0; PAGE_SIZE * PAGE_COUNTS / 8]);
+        let base = &test_mem.0[0] as *const _ as usize;
+        let bottom = base + PAGE_SIZE;
+        let top = base + PAGE_SIZE * PAGE_COUNTS;
+
+        let mut buddy: BuddyAllocator = BuddyAllocator::new();
+        unsafe { buddy.init(bottom, top) };
+
+        let available = buddy.huge_pages_available(HUGE_ORDER);
+        assert_eq!(PAGE_COUNTS / 4 - 1, available);
+
+        let addr = unsafe { buddy.allocate_huge(HUGE_ORDER) }.unwrap();
+        assert_eq!(base + HUGE_SIZE, addr);
+        assert_eq!(available - 1, buddy.huge_pages_available(HUGE_ORDER));
+        unsafe { buddy.deallocate_huge(addr, HUGE_ORDER) }.unwrap();
+        assert_eq!(available, buddy.huge_pages_available(HUGE_ORDER));
+
+        // 预留的大页不会被普通分配使用
+        assert_eq!(2, unsafe { buddy.reserve_huge(HUGE_ORDER, 2) }.unwrap());
+        let page = Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap();
+        for _ in 0..(PAGE_COUNTS - 2 - 2 * 4) {
+            assert!(unsafe { buddy.allocate(page) }.is_ok());
         }
+        assert!(unsafe { buddy.allocate(page) }.is_err());
+        assert_eq!(2, buddy.huge_pages_available(HUGE_ORDER));
+
+        let addr = unsafe { buddy.allocate_huge(HUGE_ORDER) }.unwrap();
+        assert!(is_align!(addr, HUGE_SIZE));
+        assert_eq!(1, buddy.huge_pages_available(HUGE_ORDER));
+        unsafe { buddy.deallocate_huge(addr, HUGE_ORDER) }.unwrap();
+        assert_eq!(2, buddy.huge_pages_available(HUGE_ORDER));
+
+        // 重复释放到预留池
+        assert!(matches!(
+            unsafe { buddy.deallocate_huge(addr, HUGE_ORDER) },
+            Err(BuddyErr::DoubleFree)
+        ));
+        assert_eq!(2, buddy.huge_pages_available(HUGE_ORDER));
+        // 预留池中的页不能通过普通接口释放
+        assert!(matches!(
+            unsafe { buddy.deallocate(addr, PAGE_SIZE) },
+            Err(BuddyErr::DoubleFree)
+        ));
+        assert!(matches!(
+            unsafe { buddy.deallocate(addr + PAGE_SIZE * 3, PAGE_SIZE) },
+            Err(BuddyErr::DoubleFree)
+        ));
+        assert_eq!(2, buddy.huge_pages_available(HUGE_ORDER));
+
+        // 大页不足时撤销本次预留，预留池保持原样
+        let free = buddy.free_pages();
+        assert!(unsafe { buddy.reserve_huge(HUGE_ORDER, 3) }.is_err());
+        assert_eq!(free, buddy.free_pages());
+        assert_eq!(2, buddy.huge_pages_available(HUGE_ORDER));
+        let addr = unsafe { buddy.allocate_huge(HUGE_ORDER) }.unwrap();
+        unsafe { buddy.deallocate_huge(addr, HUGE_ORDER) }.unwrap();
+        assert_eq!(free, buddy.free_pages());
+        assert_eq!(2, buddy.huge_pages_available(HUGE_ORDER));
+
+        assert_eq!(0, unsafe { buddy.reserve_huge(HUGE_ORDER, 0) }.unwrap());
+        assert_eq!(2, buddy.huge_pages_available(HUGE_ORDER));
+    }
+
+    #[test]
+    fn resize_test() {
+        const PAGE_COUNTS: usize = 32;
+
+        #[repr(C, align(8192))]
+        struct TestMem([usize; PAGE_SIZE * PAGE_COUNTS / 8]);
+
+        let test_mem = TestMem([0; PAGE_SIZE * PAGE_COUNTS / 8]);
+        let bottom = &test_mem.0[0] as *const _ as usize;
+        let top = bottom + PAGE_SIZE * PAGE_COUNTS;
+
+        let mut buddy: BuddyAllocator = BuddyAllocator::new();
+        unsafe { buddy.init(bottom, top) };
+        let free = buddy.free_pages();
+
+        let four = Layout::from_size_align(PAGE_SIZE * 4, PAGE_SIZE).unwrap();
+        let two = Layout::from_size_align(PAGE_SIZE * 2, PAGE_SIZE).unwrap();
+        let addr = unsafe { buddy.allocate(four) }.unwrap();
+
+        // 原地减小，释放后半部分的伙伴
+        unsafe { buddy.resize(addr, PAGE_SIZE * 4, PAGE_SIZE * 2) }.unwrap();
+        assert_eq!(free - 2, buddy.free_pages());
+
+        // 相邻的伙伴空闲，原地增大
+        unsafe { buddy.resize(addr, PAGE_SIZE * 2, PAGE_SIZE * 4) }.unwrap();
+        assert_eq!(free - 4, buddy.free_pages());
+        let other = unsafe { buddy.allocate(two) }.unwrap();
+        assert!(other >= addr + PAGE_SIZE * 4 || other + PAGE_SIZE * 2 <= addr);
+        assert_eq!(free - 6, buddy.free_pages());
+
+        // 地址不是新大小对应节点的起始地址
+        assert!(unsafe { buddy.resize(addr + PAGE_SIZE, PAGE_SIZE, PAGE_SIZE * 4) }.is_err());
+
+        unsafe { buddy.resize(addr, PAGE_SIZE * 4, PAGE_SIZE) }.unwrap();
+        assert_eq!(free - 3, buddy.free_pages());
+        unsafe { buddy.deallocate(addr, PAGE_SIZE) }.unwrap();
+        unsafe { buddy.deallocate(other, PAGE_SIZE * 2) }.unwrap();
+        assert_eq!(free, buddy.free_pages());
+        let addr = unsafe { buddy.allocate(four) }.unwrap();
+        assert!(is_align!(addr - bottom, PAGE_SIZE * 4));
+        assert_eq!(free - 4, buddy.free_pages());
+    }
+
+    #[test]
+    fn double_free_test() {
+        const PAGE_COUNTS: usize = 32;
+
+        #[repr(C, align(8192))]
+        struct TestMem([usize; PAGE_SIZE * PAGE_COUNTS / 8]);
+
+        let test_mem = TestMem([0; PAGE_SIZE * PAGE_COUNTS / 8]);
+        let bottom = &test_mem.0[0] as *const _ as usize;
+        let top = bottom + PAGE_SIZE * PAGE_COUNTS;
+
+        let mut buddy: BuddyAllocator = BuddyAllocator::new();
+        unsafe { buddy.init(bottom, top) };
+        let free = buddy.free_pages();
+
+        let two = Layout::from_size_align(PAGE_SIZE * 2, PAGE_SIZE).unwrap();
+        let addr = unsafe { buddy.allocate(two) }.unwrap();
+        unsafe { buddy.deallocate(addr, PAGE_SIZE * 2) }.unwrap();
+        assert_eq!(free, buddy.free_pages());
+
+        // 重复释放
+        assert!(matches!(
+            unsafe { buddy.deallocate(addr, PAGE_SIZE * 2) },
+            Err(BuddyErr::DoubleFree)
+        ));
+        // 地址不在被管理的范围内
+        assert!(matches!(
+            unsafe { buddy.deallocate(top + PAGE_SIZE * 64, PAGE_SIZE) },
+            Err(BuddyErr::OutOfZone)
+        ));
+        // 地址在被管理的内存之前
+        assert!(matches!(
+            unsafe { buddy.deallocate(buddy.zone_start() - PAGE_SIZE, PAGE_SIZE) },
+            Err(BuddyErr::OutOfZone)
+        ));
+        // 地址加大小溢出
+        let huge = usize::MAX & !(PAGE_SIZE - 1);
+        assert!(matches!(
+            unsafe { buddy.deallocate(addr, huge) },
+            Err(BuddyErr::OutOfZone)
+        ));
+        assert_eq!(free, buddy.free_pages());
     }
 }
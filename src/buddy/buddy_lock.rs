@@ -0,0 +1,201 @@
+use super::{buddy_allocator::BuddyAllocator, def::MemPtr};
+use crate::{
+    align_up,
+    def::dangling,
+    is_align,
+    lock::{Lock, RawLock, SpinLock},
+};
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    ptr::{copy_nonoverlapping, NonNull},
+};
+
+/// 加锁的页内存分配器
+/// 可以作为独立的堆，例如 Vec<u8, &LockedBuddy>
+/// 每次分配都会得到2的幂个页
+/// L 与 LockedSlab 相同，需要在中断中分配时可以使用 IrqLock
+pub struct LockedBuddy<
+    const PGSZ: usize = { crate::def::PGSZ },
+    const MAX_ORDER: usize = { crate::def::MAX_ORDER },
+    L: RawLock = SpinLock,
+>(Lock<L, BuddyAllocator<PGSZ, MAX_ORDER>>);
+
+impl<const PGSZ: usize, const MAX_ORDER: usize, L: RawLock> LockedBuddy<PGSZ, MAX_ORDER, L> {
+    pub const fn new_uninit() -> Self {
+        LockedBuddy(Lock::new(BuddyAllocator::new()))
+    }
+
+    /// # Safety
+    /// bottom 到 top 之间的内存需要可用且不被其他人使用
+    pub unsafe fn init(&self, bottom: MemPtr, top: MemPtr) {
+        self.0.lock().init(bottom, top)
+    }
+
+    // 剩余空闲页数
+    pub fn free_pages(&self) -> usize {
+        self.0.lock().free_pages()
+    }
+
+    // layout实际占用的块
+    fn block_layout(layout: Layout) -> Result<Layout, AllocError> {
+        let size = align_up!(layout.size(), PGSZ).next_power_of_two();
+        Layout::from_size_align(size, core::cmp::max(layout.align(), PGSZ)).map_err(|_| AllocError)
+    }
+
+    // 调整内存大小，无法原地调整时分配新内存并复制
+    unsafe fn reallocate(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if old_layout.size() == 0 {
+            return self.allocate(new_layout);
+        } else if new_layout.size() == 0 {
+            self.deallocate(ptr, old_layout);
+            return Ok(NonNull::slice_from_raw_parts(dangling(new_layout), 0));
+        }
+
+        let old = Self::block_layout(old_layout)?;
+        let new = Self::block_layout(new_layout)?;
+        let addr = ptr.as_ptr() as MemPtr;
+        let mut buddy = self.0.lock();
+        if is_align!(addr, new.align()) && buddy.resize(addr, old.size(), new.size()).is_ok() {
+            return Ok(NonNull::slice_from_raw_parts(ptr, new.size()));
+        }
+
+        let new_addr = buddy.allocate(new).map_err(|_| AllocError)?;
+        copy_nonoverlapping(
+            ptr.as_ptr(),
+            new_addr as *mut u8,
+            core::cmp::min(old_layout.size(), new_layout.size()),
+        );
+        buddy
+            .deallocate(addr, old.size())
+            .expect("error the buddy free error");
+
+        let new_ptr = NonNull::new_unchecked(new_addr as *mut u8);
+        Ok(NonNull::slice_from_raw_parts(new_ptr, new.size()))
+    }
+}
+
+unsafe impl<const PGSZ: usize, const MAX_ORDER: usize, L: RawLock> Allocator
+    for &LockedBuddy<PGSZ, MAX_ORDER, L>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            return Ok(NonNull::slice_from_raw_parts(dangling(layout), 0));
+        }
+
+        let block = LockedBuddy::<PGSZ, MAX_ORDER, L>::block_layout(layout)?;
+        let addr = unsafe { self.0.lock().allocate(block) }.map_err(|_| AllocError)?;
+        let ptr = NonNull::new(addr as *mut u8).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, block.size()))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.allocate(layout)?;
+        unsafe { (ptr.as_ptr() as *mut u8).write_bytes(0, ptr.len()) };
+        Ok(ptr)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+
+        let block = LockedBuddy::<PGSZ, MAX_ORDER, L>::block_layout(layout).expect("wrong layout");
+        self.0
+            .lock()
+            .deallocate(ptr.as_ptr() as MemPtr, block.size())
+            .expect("error the buddy free error");
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.reallocate(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new_ptr = self.reallocate(ptr, old_layout, new_layout)?;
+        (new_ptr.as_ptr() as *mut u8)
+            .add(old_layout.size())
+            .write_bytes(0, new_ptr.len() - old_layout.size());
+        Ok(new_ptr)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.reallocate(ptr, old_layout, new_layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::LockedBuddy;
+    use crate::{
+        def::{MAX_ORDER, PGSZ},
+        lock::{RawLock, SpinLock, TicketLock},
+    };
+    use std::{boxed::Box, vec::Vec};
+
+    #[test]
+    fn allocator_test() {
+        check_allocator::<SpinLock>();
+        check_allocator::<TicketLock>();
+    }
+
+    fn check_allocator<L: RawLock>() {
+        const PAGE_COUNTS: usize = 32;
+
+        #[repr(C, align(4096))]
+        struct TestMem([usize; PGSZ * PAGE_COUNTS / 8]);
+
+        let test_mem = TestMem([0; PGSZ * PAGE_COUNTS / 8]);
+        let bottom = &test_mem.0[0] as *const _ as usize;
+        let top = bottom + PGSZ * PAGE_COUNTS;
+
+        let heap: LockedBuddy<PGSZ, MAX_ORDER, L> = LockedBuddy::new_uninit();
+        unsafe { heap.init(bottom, top) };
+        let free = heap.free_pages();
+
+        {
+            let boxed = Box::new_in(0x1234usize, &heap);
+            assert_eq!(0x1234, *boxed);
+            assert_eq!(0, &*boxed as *const _ as usize % PGSZ);
+
+            // 增长时保留内容
+            let mut vec: Vec<usize, &LockedBuddy<PGSZ, MAX_ORDER, L>> = Vec::new_in(&heap);
+            for i in 0..PGSZ {
+                vec.push(i);
+            }
+            assert!(vec.iter().enumerate().all(|(i, &v)| i == v));
+            vec.truncate(8);
+            vec.shrink_to_fit();
+            assert_eq!(&[0, 1, 2, 3, 4, 5, 6, 7], vec.as_slice());
+
+            let zeroed: Vec<u8, &LockedBuddy<PGSZ, MAX_ORDER, L>> = {
+                let mut vec = Vec::new_in(&heap);
+                vec.resize(PGSZ * 2, 0);
+                vec
+            };
+            assert!(zeroed.iter().all(|&v| v == 0));
+        }
+
+        assert_eq!(free, heap.free_pages());
+    }
+}
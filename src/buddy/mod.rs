@@ -1,2 +1,3 @@
 pub(crate) mod buddy_allocator;
+pub(crate) mod buddy_lock;
 pub(crate) mod def;
@@ -1 +1,7 @@
-pub(crate) const PGSZ: usize = 4096;
+pub(crate) const PGSZ: usize = 4096; // 默认页大小
+pub(crate) const MAX_ORDER: usize = 15; // 默认最大阶数，可管理 PGSZ << MAX_ORDER 的内存
+
+// 零大小分配使用的悬空指针，按layout对齐且非空
+pub(crate) fn dangling(layout: core::alloc::Layout) -> core::ptr::NonNull<u8> {
+    unsafe { core::ptr::NonNull::new_unchecked(layout.align() as *mut u8) }
+}
@@ -3,38 +3,132 @@
 #![feature(const_for)]
 #![feature(const_trait_impl)]
 #![feature(exclusive_range_pattern)]
+#![feature(allocator_api)]
 mod bintree;
 mod buddy;
 mod def;
 mod linklist;
+mod lock;
 mod macros;
+#[cfg(any(feature = "hardened", feature = "randomize"))]
+mod rand;
 mod slab;
+pub mod trace;
 
 //pub use bintree::treemap::TreeMap;
-pub use buddy::buddy_allocator::BuddyAllocator;
+pub use buddy::buddy_allocator::{BuddyAllocator, BuddyErr};
+pub use buddy::buddy_lock::LockedBuddy;
+pub use lock::{IrqHooks, IrqLock, Lock, LockGuard, RawLock, SpinLock, TicketLock};
+pub use slab::cache::{CacheId, Ctor};
+pub use slab::class::{SizeClasses, FINE_CLASSES, POW2_CLASSES};
+#[cfg(feature = "debug")]
+pub use slab::debug::{CorruptKind, Corruption};
+pub use slab::def::{Shrinker, SlabErr, ViolationHandler};
 pub use slab::slab_lock::LockedSlab;
+pub use slab::stats::{ClassStats, SlabStats};
 
 #[cfg(test)]
 mod tests {
-    use core::alloc::{GlobalAlloc, Layout};
-    use std::println;
+    use core::{
+        alloc::{GlobalAlloc, Layout},
+        ptr::null_mut,
+    };
+    use std::{println, vec::Vec};
     use xxos_log::WriteLog;
     extern crate std;
-    use crate::{def::PGSZ, slab::slab_lock::LockedSlab};
+    use crate::{align_up, def::PGSZ, slab::slab_lock::LockedSlab, RawLock};
     struct PT;
     impl WriteLog for PT {
         fn print(&self, log_content: core::fmt::Arguments) {
             println!("{}", log_content)
         }
     }
-    #[test]
-    fn test_alloc_small() {
-        let heap_arr = [0usize; 4096 * 10];
+
+    // 在栈上的一段内存中运行测试，参数为内存的起止地址
+    // 按页对齐，使得分配结果与栈地址无关
+    fn with_zone(f: impl FnOnce(usize, usize)) {
+        #[repr(C, align(4096))]
+        struct Zone([usize; 4096 * 10]);
+
+        let zone = Zone([0; 4096 * 10]);
+        let heap_arr = &zone.0;
         let bottom = &heap_arr[0] as *const _ as usize;
         let top = &heap_arr[4096 * 10 - 1] as *const _ as usize;
-        unsafe {
-            let heap = LockedSlab::new_uninit();
-            heap.init(bottom, top);
+        f(bottom, top)
+    }
+
+    // 在堆上的一段较大的内存中运行测试，需要多个超过最大类别的页块时使用，
+    // 随机化时元数据和slab分散在内存区中，较小的内存区可能没有足够大的空闲块
+    fn with_large_zone(f: impl FnOnce(usize, usize)) {
+        let heap_vec = std::vec![0usize; 4096 * 64];
+        let bottom = heap_vec.as_ptr() as usize;
+        f(bottom, bottom + heap_vec.len() * 8)
+    }
+
+    // 在栈上的一段内存中初始化默认配置的分配器并运行测试
+    fn with_heap(f: impl FnOnce(&LockedSlab)) {
+        with_zone(|bottom, top| {
+            let heap: LockedSlab = LockedSlab::new_uninit();
+            init_heap(&heap, bottom, top);
+            f(&heap)
+        })
+    }
+
+    // 初始化分配器，加固模式下还需要在分配前设置密钥
+    fn init_heap<const P: usize, const M: usize, L: RawLock>(
+        heap: &LockedSlab<P, M, L>,
+        bottom: usize,
+        top: usize,
+    ) {
+        heap.init(bottom, top);
+        #[cfg(feature = "hardened")]
+        heap.set_secret(0x5eed_1234);
+    }
+
+    // 从一个新的slab中分配直到用完，返回按地址排序的对象和slab占用的页数
+    // 对象的位置与调试模式的保护区和随机化无关，只检查同一slab中对象的相对位置
+    unsafe fn fill_slab<const P: usize, const M: usize, L: RawLock>(
+        heap: &LockedSlab<P, M, L>,
+        layout: Layout,
+    ) -> (Vec<usize>, usize) {
+        // 无锁栈中之前释放的对象不属于新的slab
+        #[cfg(feature = "lock_free")]
+        heap.drain_free_stacks();
+        let free = heap.free_pages();
+        let mut objs = std::vec![heap.alloc(layout) as usize];
+        let pages = free - heap.free_pages();
+        loop {
+            let ptr = heap.alloc(layout);
+            if ptr.is_null() {
+                break;
+            }
+            if free - heap.free_pages() != pages {
+                heap.dealloc(ptr, layout);
+                break;
+            }
+            objs.push(ptr as usize);
+        }
+        objs.sort();
+        (objs, pages)
+    }
+
+    // 排序后的对象间隔都为stride
+    fn adjacent(objs: &[usize], stride: usize) -> bool {
+        objs.windows(2).all(|pair| pair[0] + stride == pair[1])
+    }
+    #[test]
+    fn test_alloc_small() {
+        with_heap(|heap| unsafe {
+            // 290字节的对象在512的类别中，同一slab中的对象相邻
+            let layout = Layout::from_size_align(290, 8).unwrap();
+            let (objs, pages) = fill_slab(heap, layout);
+            assert_eq!((PGSZ / 512, 1), (objs.len(), pages));
+            assert!(adjacent(&objs, 512));
+        });
+
+        // 未随机化时对象和slab都按地址顺序分配，加固模式下页描述符更大，slab的位置不同
+        #[cfg(not(any(feature = "randomize", feature = "hardened")))]
+        with_heap(|heap| unsafe {
             let mut now = 0;
             for i in 0..10 {
                 let last = now;
@@ -44,17 +138,12 @@ mod tests {
                     assert_eq!(last + 512, now);
                 }
             }
-        }
+        });
     }
 
     #[test]
     fn test_free_small() {
-        let heap_arr = [0usize; 4096 * 10];
-        let bottom = &heap_arr[0] as *const _ as usize;
-        let top = &heap_arr[4096 * 10 - 1] as *const _ as usize;
-        unsafe {
-            let heap = LockedSlab::new_uninit();
-            heap.init(bottom, top);
+        with_heap(|heap| unsafe {
             let mut now = 0;
             for i in 0..10 {
                 let last = now;
@@ -65,17 +154,25 @@ mod tests {
                     assert_eq!(last, now);
                 }
             }
-        }
+        });
     }
 
     #[test]
     fn test_alloc_big() {
-        let heap_arr = [0usize; 4096 * 10];
-        let bottom = &heap_arr[0] as *const _ as usize;
-        let top = &heap_arr[4096 * 10 - 1] as *const _ as usize;
-        unsafe {
-            let heap = LockedSlab::new_uninit();
-            heap.init(bottom, top);
+        with_heap(|heap| unsafe {
+            // 页块之间不重叠，相对位置按块大小对齐
+            let layout = Layout::from_size_align(PGSZ * 2, 8).unwrap();
+            let mut blocks = [0; 3].map(|_| heap.alloc(layout) as usize);
+            blocks.sort();
+            for pair in blocks.windows(2) {
+                assert!(pair[0] < pair[1]);
+                assert_eq!(0, (pair[1] - pair[0]) % (PGSZ * 2));
+            }
+        });
+
+        // 未随机化时两页的对象按地址顺序分配，调试模式下带保护区的对象位于更大的类别中
+        #[cfg(not(any(feature = "randomize", feature = "debug")))]
+        with_heap(|heap| unsafe {
             let mut now = 0;
             for i in 0..3 {
                 let last = now;
@@ -85,17 +182,27 @@ mod tests {
                     assert_eq!(last + PGSZ * 2, now);
                 }
             }
-        }
+        });
     }
 
     #[test]
     fn test_free_big() {
-        let heap_arr = [0usize; 4096 * 10];
-        let bottom = &heap_arr[0] as *const _ as usize;
-        let top = &heap_arr[4096 * 10 - 1] as *const _ as usize;
-        unsafe {
-            let heap = LockedSlab::new_uninit();
-            heap.init(bottom, top);
+        with_heap(|heap| unsafe {
+            let free = heap.free_pages();
+            // 超过最大类别的内存直接从页内存分配器分配
+            for _ in 0..3 {
+                let layout = Layout::from_size_align(PGSZ * 16, 8).unwrap();
+                let ptr = heap.alloc(layout);
+                assert!(!ptr.is_null());
+                assert_eq!(free - 16, heap.free_pages());
+                heap.dealloc(ptr, layout);
+                assert_eq!(free, heap.free_pages());
+            }
+        });
+
+        // 未随机化时释放的块被再次分配
+        #[cfg(not(feature = "randomize"))]
+        with_heap(|heap| unsafe {
             let mut now = 0;
             for i in 0..3 {
                 let last = now;
@@ -106,6 +213,931 @@ mod tests {
                     assert_eq!(last, now);
                 }
             }
+        });
+    }
+
+    #[test]
+    fn test_alloc_16k_page() {
+        const PAGE_16K: usize = PGSZ * 4;
+        with_large_zone(|bottom, top| unsafe {
+            let heap: LockedSlab<PAGE_16K> = LockedSlab::new_uninit();
+            init_heap(&heap, bottom, top);
+            let small = Layout::from_size_align(290, 8).unwrap();
+            let (objs, pages) = fill_slab(&heap, small);
+            assert_eq!((PAGE_16K / 512, 1), (objs.len(), pages));
+            assert!(adjacent(&objs, 512));
+
+            let big = Layout::from_size_align(PAGE_16K * 4, 8).unwrap();
+            let free = heap.free_pages();
+            let now = heap.alloc(big) as usize;
+            assert_eq!(0, now % PAGE_16K);
+            heap.dealloc(now as *mut _, big);
+            assert_eq!(free, heap.free_pages());
+        });
+    }
+
+    #[test]
+    fn test_object_cache() {
+        use crate::SlabErr;
+
+        fn ctor(obj: *mut u8) {
+            unsafe { (obj as *mut u64).write(0xdead_beef) };
         }
+
+        with_heap(|heap| unsafe {
+            let id = heap.create_cache("task", 24, 8, Some(ctor)).unwrap();
+            assert_eq!(Some(id), heap.find_cache("task"));
+
+            let obj = heap.cache_alloc(id).unwrap();
+            assert_eq!(0, obj as usize % 8);
+            assert_eq!(0xdead_beef, *(obj as *mut u64));
+
+            // 释放后对象保持已构造的状态
+            heap.cache_free(id, obj).unwrap();
+            assert_eq!(0xdead_beef, *(obj as *mut u64));
+            assert_eq!(obj, heap.cache_alloc(id).unwrap());
+
+            assert!(heap.destroy_cache(id).is_err());
+            heap.cache_free(id, obj).unwrap();
+            assert!(heap.destroy_cache(id).is_ok());
+            assert_eq!(None, heap.find_cache("task"));
+            assert!(heap.cache_alloc(id).is_err());
+
+            // 旧的编号不能用于占用同一个槽的新缓存
+            let new = heap.create_cache("file", 24, 8, None).unwrap();
+            let obj = heap.cache_alloc(new).unwrap();
+            assert!(matches!(heap.cache_free(id, obj), Err(SlabErr::NotFound)));
+
+            // 不属于该缓存的指针
+            let other = heap.alloc(Layout::from_size_align(24, 8).unwrap());
+            assert!(matches!(
+                heap.cache_free(new, other),
+                Err(SlabErr::InvalidFree)
+            ));
+            assert!(matches!(
+                heap.cache_free(new, obj.add(8)),
+                Err(SlabErr::InvalidFree)
+            ));
+            heap.cache_free(new, obj).unwrap();
+
+            // 重复释放不会使正在使用的对象数出错，缓存仍能销毁
+            assert!(matches!(
+                heap.cache_free(new, obj),
+                Err(SlabErr::DoubleFree)
+            ));
+            assert!(heap.destroy_cache(new).is_ok());
+
+            // 缓存中的对象也可以不经过编号释放
+            let new = heap.create_cache("inode", 40, 8, None).unwrap();
+            let obj = heap.cache_alloc(new).unwrap();
+            assert!(heap.free(obj).is_ok());
+            assert!(matches!(heap.free(obj), Err(SlabErr::DoubleFree)));
+            assert!(heap.destroy_cache(new).is_ok());
+            assert!(matches!(heap.free(obj), Err(SlabErr::InvalidFree)));
+            heap.dealloc(other, Layout::from_size_align(24, 8).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_release_empty_slab() {
+        with_heap(|heap| unsafe {
+            let free = heap.free_pages();
+
+            // 占用三页以上64字节的对象，再全部释放
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let mut objs = [null_mut(); PGSZ / 64 * 3];
+            for obj in objs.iter_mut() {
+                *obj = heap.alloc(layout);
+            }
+            let used = free - heap.free_pages();
+            assert!(used >= 3);
+            assert_eq!(used, heap.stats().classes().iter().map(|c| c.pages).sum());
+
+            for obj in objs.iter() {
+                heap.dealloc(*obj, layout);
+            }
+            // 无锁模式下释放的对象先留在栈中
+            #[cfg(feature = "lock_free")]
+            heap.drain_free_stacks();
+            // 默认保留一个空slab
+            assert_eq!(free - 1, heap.free_pages());
+
+            heap.set_empty_limit(0);
+            let obj = heap.alloc(layout);
+            heap.dealloc(obj, layout);
+            #[cfg(feature = "lock_free")]
+            heap.drain_free_stacks();
+            assert_eq!(free, heap.free_pages());
+        });
+    }
+
+    #[test]
+    fn test_shrink() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        with_heap(|heap| unsafe {
+            heap.set_empty_limit(8);
+            #[cfg(feature = "debug")]
+            heap.set_quarantine(0);
+            heap.register_shrinker(|| {
+                CALLS.fetch_add(1, Ordering::Relaxed);
+            })
+            .unwrap();
+            let free = heap.free_pages();
+
+            // 释放后空slab留在内存池中，回收时全部归还
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let mut objs = [null_mut(); PGSZ / 64 * 3];
+            for obj in objs.iter_mut() {
+                *obj = heap.alloc(layout);
+            }
+            for obj in objs.iter() {
+                heap.dealloc(*obj, layout);
+            }
+            let cached = free - heap.free_pages();
+            assert!(cached > 0);
+            assert_eq!(cached, heap.shrink());
+            assert_eq!(free, heap.free_pages());
+            assert_eq!(1, CALLS.load(Ordering::Relaxed));
+
+            // 页内存分配失败前自动回收空slab
+            for obj in objs.iter_mut() {
+                *obj = heap.alloc(layout);
+            }
+            for obj in objs.iter() {
+                heap.dealloc(*obj, layout);
+            }
+            let big = Layout::from_size_align(PGSZ * 16, PGSZ).unwrap();
+            while !heap.alloc(big).is_null() {}
+            assert!(heap.stats().classes().iter().all(|class| class.pages == 0));
+            assert!(CALLS.load(Ordering::Relaxed) > 1);
+        });
+    }
+
+    #[test]
+    fn test_shrink_recursive() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        static HEAP: LockedSlab = LockedSlab::new_uninit();
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        with_zone(|bottom, top| {
+            init_heap(&HEAP, bottom, top);
+            // 收缩回调中再次回收时直接返回，不会递归调用回调
+            HEAP.register_shrinker(|| {
+                CALLS.fetch_add(1, Ordering::Relaxed);
+                assert_eq!(0, HEAP.shrink());
+            })
+            .unwrap();
+            HEAP.shrink();
+            assert_eq!(1, CALLS.load(Ordering::Relaxed));
+            HEAP.shrink();
+            assert_eq!(2, CALLS.load(Ordering::Relaxed));
+        });
+    }
+
+    #[test]
+    fn test_free_without_layout() {
+        use crate::SlabErr;
+
+        with_heap(|heap| unsafe {
+            heap.set_empty_limit(0);
+            #[cfg(feature = "debug")]
+            heap.set_quarantine(0);
+            let free = heap.free_pages();
+
+            // 根据页描述符找到对象所属的类别和页块
+            let small = heap.alloc(Layout::from_size_align(64, 8).unwrap());
+            let big = heap.alloc(Layout::from_size_align(PGSZ * 2, PGSZ).unwrap());
+            assert!(matches!(
+                heap.free(big.add(PGSZ)),
+                Err(SlabErr::InvalidFree)
+            ));
+            // 对象内部的地址不是分配时返回的地址，对象仍在使用中
+            assert!(matches!(heap.free(small.add(1)), Err(SlabErr::InvalidFree)));
+            assert!(heap.free(small).is_ok());
+            assert!(heap.free(big).is_ok());
+            #[cfg(feature = "lock_free")]
+            heap.drain_free_stacks();
+            assert_eq!(free, heap.free_pages());
+            assert!(heap.stats().classes().iter().all(|class| class.live == 0));
+
+            // 不知道请求的大小时按平均值扣除，请求的字节数不会回绕
+            #[cfg(not(feature = "debug"))]
+            {
+                let layout = Layout::from_size_align(100, 8).unwrap();
+                let ptr1 = heap.alloc(layout);
+                let ptr2 = heap.alloc(layout);
+                assert!(heap.free(ptr1).is_ok());
+                assert_eq!(28, heap.stats().get(128).unwrap().fragment);
+                assert!(heap.free(ptr2).is_ok());
+                let ptr3 = heap.alloc(layout);
+                assert_eq!(28, heap.stats().get(128).unwrap().fragment);
+                heap.dealloc(ptr3, layout);
+            }
+        });
+    }
+
+    #[test]
+    fn test_usable_size() {
+        with_heap(|heap| unsafe {
+            // 290字节实际可以使用512字节，调试模式下之后的字节属于保护区
+            let class = if cfg!(feature = "debug") { 290 } else { 512 };
+            let layout = Layout::from_size_align(290, 8).unwrap();
+            let ptr = heap.alloc(layout);
+            assert_eq!(Some(class), heap.usable_size(ptr));
+            let (sized, size) = heap.alloc_sized(layout).unwrap();
+            assert_eq!(class, size);
+            sized.write_bytes(0x5a, size);
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            assert!(heap.dealloc_checked(sized, layout).is_ok());
+            heap.dealloc(ptr, Layout::from_size_align(290, 8).unwrap());
+
+            // 按可用大小释放后请求的字节数不会回绕
+            #[cfg(not(feature = "debug"))]
+            {
+                let layout = Layout::from_size_align(290, 8).unwrap();
+                let usable = Layout::from_size_align(512, 8).unwrap();
+                let ptr1 = heap.alloc(layout);
+                let ptr2 = heap.alloc(layout);
+                heap.dealloc(ptr1, usable);
+                heap.dealloc(ptr2, usable);
+                let ptr = heap.alloc(layout);
+                assert_eq!(512 - 290, heap.stats().get(512).unwrap().fragment);
+                heap.dealloc(ptr, layout);
+            }
+
+            // 页块按页取整到2的幂个页
+            let layout = Layout::from_size_align(PGSZ * 8 + 100, 8).unwrap();
+            let ptr = heap.alloc(layout);
+            assert_eq!(Some(PGSZ * 16), heap.usable_size(ptr));
+            // 可用的整个块不会与其他分配重叠
+            ptr.write_bytes(0x5a, PGSZ * 16);
+            let page = heap.alloc(Layout::from_size_align(PGSZ, PGSZ).unwrap()) as usize;
+            assert!(page + PGSZ <= ptr as usize || page >= ptr as usize + PGSZ * 16);
+            assert_eq!(None, heap.usable_size(ptr.add(PGSZ)));
+            assert_eq!(None, heap.usable_size(null_mut()));
+        });
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        use crate::SlabErr;
+
+        with_heap(|heap| unsafe {
+            // layout属于其他类别或页数不同时不释放
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let ptr = heap.alloc(layout);
+            let wrong = Layout::from_size_align(256, 8).unwrap();
+            assert!(matches!(
+                heap.dealloc_checked(ptr, wrong),
+                Err(SlabErr::SizeMismatch)
+            ));
+            assert!(heap.dealloc_checked(ptr, layout).is_ok());
+
+            let layout = Layout::from_size_align(PGSZ * 2, PGSZ).unwrap();
+            let ptr = heap.alloc(layout);
+            let wrong = Layout::from_size_align(PGSZ * 4, PGSZ).unwrap();
+            assert!(matches!(
+                heap.dealloc_checked(ptr, wrong),
+                Err(SlabErr::SizeMismatch)
+            ));
+            assert!(heap.dealloc_checked(ptr, layout).is_ok());
+        });
+    }
+
+    #[test]
+    fn test_double_free() {
+        use crate::{slab::slab_allocator::SlabAllocator, SlabErr};
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        static DOUBLE: AtomicUsize = AtomicUsize::new(0);
+        fn handler(err: SlabErr, _: *mut u8) {
+            if let SlabErr::DoubleFree = err {
+                DOUBLE.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        with_heap(|heap| unsafe {
+            heap.set_violation_handler(handler);
+
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let ptr = heap.alloc(layout);
+            assert!(heap.dealloc_checked(ptr, layout).is_ok());
+            assert!(matches!(
+                heap.dealloc_checked(ptr, layout),
+                Err(SlabErr::DoubleFree)
+            ));
+            assert!(matches!(heap.free(ptr), Err(SlabErr::DoubleFree)));
+
+            // 重复释放的对象不会被分配两次
+            let a = heap.alloc(layout);
+            let b = heap.alloc(layout);
+            assert_ne!(a, b);
+
+            // 不是对象起始地址或不属于分配器的指针
+            assert!(matches!(
+                heap.dealloc_checked(a.add(8), layout),
+                Err(SlabErr::InvalidFree)
+            ));
+            let local = 0usize;
+            assert!(matches!(
+                heap.free(&local as *const _ as *mut u8),
+                Err(SlabErr::InvalidFree)
+            ));
+
+            // 通过 GlobalAlloc 释放时调用违规处理函数
+            heap.dealloc(a, layout);
+            heap.dealloc(a, layout);
+            assert_eq!(1, DOUBLE.load(Ordering::Relaxed));
+            heap.dealloc(b, layout);
+            assert_eq!(1, DOUBLE.load(Ordering::Relaxed));
+
+            // 页块重复释放
+            let layout = Layout::from_size_align(PGSZ * 2, PGSZ).unwrap();
+            let ptr = heap.alloc(layout);
+            assert!(heap.dealloc_checked(ptr, layout).is_ok());
+            assert!(heap.dealloc_checked(ptr, layout).is_err());
+        });
+
+        // SlabAllocator 同样检查使用位图，中间释放了其他对象也能发现重复释放
+        let mem = std::vec![0usize; 4096 * 10];
+        let bottom = mem.as_ptr() as usize;
+        let mut slab: SlabAllocator = SlabAllocator::new();
+        unsafe {
+            slab.init(bottom, bottom + mem.len() * 8);
+            #[cfg(feature = "hardened")]
+            slab.set_secret(0x5eed_1234);
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let a = slab.allocate_fit(layout).unwrap();
+            let b = slab.allocate_fit(layout).unwrap();
+            slab.deallocate_fit(a, layout).unwrap();
+            assert!(matches!(
+                slab.deallocate_fit(a, layout),
+                Err(SlabErr::DoubleFree)
+            ));
+            slab.deallocate_fit(b, layout).unwrap();
+            assert!(matches!(
+                slab.deallocate_fit(a, layout),
+                Err(SlabErr::DoubleFree)
+            ));
+            assert!(matches!(slab.free(b), Err(SlabErr::DoubleFree)));
+            let c = slab.allocate_fit(layout).unwrap();
+            assert!(slab.free(c).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_prefer_partial_slab() {
+        with_heap(|heap| unsafe {
+            // 第一个slab用满，第二个slab只使用一个对象
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let mut objs = [null_mut(); PGSZ / 64 + 1];
+            for obj in objs.iter_mut() {
+                *obj = heap.alloc(layout);
+            }
+            let free = heap.free_pages();
+
+            // 第一个slab变为部分使用后，优先从中分配
+            heap.dealloc(objs[3], layout);
+            assert_eq!(objs[3], heap.alloc(layout));
+            assert_eq!(free, heap.free_pages());
+        });
+    }
+
+    #[test]
+    fn test_fine_classes() {
+        with_zone(|bottom, top| unsafe {
+            let heap: LockedSlab = LockedSlab::with_classes(&crate::FINE_CLASSES);
+            init_heap(&heap, bottom, top);
+
+            // 8字节的对象在8的类别中，调试模式下对象位于容纳保护区的更大类别中
+            let layout = Layout::from_size_align(8, 8).unwrap();
+            let (objs, _) = fill_slab(&heap, layout);
+            assert!(adjacent(&objs, objs[1] - objs[0]));
+            #[cfg(not(feature = "debug"))]
+            assert_eq!(8, objs[1] - objs[0]);
+            assert_eq!(objs.len(), heap.stats().get(8).unwrap().live);
+
+            // 130字节落在192的类别中
+            let layout = Layout::from_size_align(130, 8).unwrap();
+            let ptr1 = heap.alloc(layout);
+            let ptr2 = heap.alloc(layout);
+            assert_eq!(2, heap.stats().get(192).unwrap().live);
+            heap.dealloc(ptr2, layout);
+            assert_eq!(ptr2, heap.alloc(layout));
+            heap.dealloc(ptr1, layout);
+        });
+    }
+
+    #[test]
+    fn test_large_classes_odd_page() {
+        with_zone(|bottom, top| unsafe {
+            // 内存区从奇数页开始，多页slab的起始地址只按页对齐
+            let bottom = align_up!(bottom, PGSZ * 2) + PGSZ;
+            let heap: LockedSlab = LockedSlab::with_classes(&crate::FINE_CLASSES);
+            init_heap(&heap, bottom, top);
+            heap.set_empty_limit(0);
+
+            for size in [8192, 16384, 24576, 32768] {
+                let layout = Layout::from_size_align(size, 8).unwrap();
+                let ptr = heap.alloc(layout);
+                assert!(!ptr.is_null());
+                // 调试模式下对象前有保护区
+                #[cfg(not(feature = "debug"))]
+                assert_eq!(0, ptr as usize % PGSZ);
+                ptr.write_bytes(0xa5, size);
+                heap.dealloc(ptr, layout);
+            }
+
+            // 对象从slab的起始处存放，不会因对齐而少一个对象
+            #[cfg(not(feature = "debug"))]
+            {
+                let layout = Layout::from_size_align(24576, 8).unwrap();
+                let (objs, pages) = fill_slab(&heap, layout);
+                assert_eq!(pages * PGSZ / 24576, objs.len());
+            }
+        });
+    }
+
+    #[test]
+    fn test_multi_page_slab() {
+        with_zone(|bottom, top| unsafe {
+            let heap: LockedSlab = LockedSlab::with_classes(&crate::FINE_CLASSES);
+            init_heap(&heap, bottom, top);
+            heap.set_empty_limit(0);
+
+            // 3072的类别使用4页的slab，容纳5个对象
+            let layout = Layout::from_size_align(3000, 8).unwrap();
+            let (objs, pages) = fill_slab(&heap, layout);
+            assert_eq!((5, 4), (objs.len(), pages));
+            assert!(adjacent(&objs, 3072));
+
+            // 6144的类别使用8页的slab，容纳5个对象
+            let layout = Layout::from_size_align(6000, 8).unwrap();
+            let (objs, pages) = fill_slab(&heap, layout);
+            assert_eq!((5, 8), (objs.len(), pages));
+            assert!(adjacent(&objs, 6144));
+            heap.dealloc(objs[1] as *mut u8, layout);
+            assert_eq!(objs[1], heap.alloc(layout) as usize);
+
+            // 无锁模式下释放的对象先留在栈中
+            #[cfg(feature = "lock_free")]
+            heap.drain_free_stacks();
+            let stats = heap.stats();
+            assert_eq!(4, stats.get(3072).unwrap().pages);
+            assert_eq!(8, stats.get(6144).unwrap().pages);
+        });
+    }
+
+    #[test]
+    fn test_natural_align() {
+        with_zone(|bottom, top| unsafe {
+            let heap: LockedSlab = LockedSlab::with_classes(&crate::FINE_CLASSES);
+            init_heap(&heap, bottom, top);
+
+            // 小对象不再按页对齐，同一slab中的对象相邻
+            let layout = Layout::from_size_align(448, 8).unwrap();
+            let (objs, _) = fill_slab(&heap, layout);
+            assert!(adjacent(&objs, 512));
+
+            // 48的类别只有16对齐，需要64对齐时使用64的类别
+            let layout = Layout::from_size_align(48, 64).unwrap();
+            let ptr1 = heap.alloc(layout) as usize;
+            let ptr2 = heap.alloc(layout) as usize;
+            assert_eq!(0, ptr1 % 64);
+            assert_eq!(0, ptr2 % 64);
+            assert_eq!(2, heap.stats().get(64).unwrap().live);
+            heap.dealloc(ptr2 as *mut _, layout);
+            assert_eq!(ptr2, heap.alloc(layout) as usize);
+
+            // 超过页大小的对齐由页内存分配器满足
+            let free = heap.free_pages();
+            let layout = Layout::from_size_align(100, PGSZ * 2).unwrap();
+            let ptr = heap.alloc(layout);
+            assert_eq!(0, ptr as usize % (PGSZ * 2));
+            heap.dealloc(ptr, layout);
+            assert_eq!(free, heap.free_pages());
+        });
+    }
+
+    #[test]
+    fn test_realloc_in_place() {
+        with_large_zone(|bottom, top| unsafe {
+            let heap: LockedSlab = LockedSlab::new_uninit();
+            init_heap(&heap, bottom, top);
+            // 同一类别内增大不需要移动，调试模式下带保护区的对象总是移动
+            let layout = Layout::from_size_align(40, 8).unwrap();
+            let ptr = heap.alloc(layout);
+            *ptr = 0x5a;
+            let grown = heap.realloc(ptr, layout, 64);
+            assert_eq!(!cfg!(feature = "debug"), ptr == grown);
+            assert_eq!(0x5a, *grown);
+
+            // 超出类别时移动并保留内容
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let moved = heap.realloc(grown, layout, 100);
+            assert_ne!(grown, moved);
+            assert_eq!(0x5a, *moved);
+
+            // 页内存原地减小释放伙伴，再与空闲的伙伴合并原地增大
+            let half = Layout::from_size_align(PGSZ * 16, PGSZ).unwrap();
+            let full = Layout::from_size_align(PGSZ * 32, PGSZ).unwrap();
+            let ptr = heap.alloc(full);
+            let free = heap.free_pages();
+            assert_eq!(ptr, heap.realloc(ptr, full, PGSZ * 16));
+            assert_eq!(free + 16, heap.free_pages());
+            assert_eq!(ptr, heap.realloc(ptr, half, PGSZ * 32));
+            assert_eq!(free, heap.free_pages());
+            heap.dealloc(ptr, full);
+            assert_eq!(free + 32, heap.free_pages());
+        });
+    }
+
+    #[test]
+    fn test_allocator_api() {
+        use std::boxed::Box;
+
+        with_heap(|heap| {
+            let boxed = Box::new_in([7u8; 100], heap);
+            assert!(boxed.iter().all(|&v| v == 7));
+
+            // 从小内存增长到页内存，内容保持不变
+            let mut vec: Vec<u32, &LockedSlab> = Vec::new_in(heap);
+            for i in 0..(PGSZ as u32) {
+                vec.push(i);
+            }
+            assert!(vec.iter().enumerate().all(|(i, &v)| i as u32 == v));
+            vec.truncate(4);
+            vec.shrink_to_fit();
+            assert_eq!(&[0, 1, 2, 3], vec.as_slice());
+
+            let zeroed: Vec<u64, &LockedSlab> = {
+                let mut vec = Vec::new_in(heap);
+                vec.resize(64, 0);
+                vec
+            };
+            assert!(zeroed.iter().all(|&v| v == 0));
+        });
+    }
+
+    #[test]
+    fn test_cpu_cache() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        static CPU: AtomicUsize = AtomicUsize::new(0);
+
+        with_heap(|heap| unsafe {
+            heap.set_empty_limit(0);
+            heap.set_cpu_hook(|| CPU.load(Ordering::Relaxed));
+            let free = heap.free_pages();
+
+            // 释放的对象留在当前CPU的magazine中，再次分配时取回
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let ptr = heap.alloc(layout);
+            heap.dealloc(ptr, layout);
+            assert_eq!(ptr, heap.alloc(layout));
+
+            // 其他CPU从共享的内存池批量补充自己的magazine
+            CPU.store(1, Ordering::Relaxed);
+            let other = heap.alloc(layout);
+            assert_ne!(ptr, other);
+            heap.dealloc(other, layout);
+            CPU.store(0, Ordering::Relaxed);
+            heap.dealloc(ptr, layout);
+            assert_eq!(free - 1, heap.free_pages());
+
+            // 归还所有缓存的对象后，空slab归还给页内存分配器
+            heap.drain_cpu(0);
+            heap.drain_cpu(1);
+            assert_eq!(free, heap.free_pages());
+        });
+    }
+
+    #[test]
+    fn test_ticket_lock_slab() {
+        use crate::{def::MAX_ORDER, TicketLock};
+
+        with_zone(|bottom, top| unsafe {
+            let heap: LockedSlab<PGSZ, MAX_ORDER, TicketLock> = LockedSlab::new_uninit();
+            init_heap(&heap, bottom, top);
+
+            let layout = Layout::from_size_align(290, 8).unwrap();
+            let (objs, _) = fill_slab(&heap, layout);
+            assert!(adjacent(&objs, 512));
+            heap.dealloc(objs[0] as *mut u8, layout);
+            assert_eq!(objs[0], heap.alloc(layout) as usize);
+        });
+    }
+
+    #[test]
+    fn test_slab_stats() {
+        extern crate alloc;
+        use alloc::string::ToString;
+
+        with_heap(|heap| unsafe {
+            // 调试模式下对象位于容纳保护区的更大类别中，统计仍按请求的大小计算
+            let layout = Layout::from_size_align(40, 8).unwrap();
+            let ptrs = [heap.alloc(layout), heap.alloc(layout), heap.alloc(layout)];
+            heap.dealloc(ptrs[0], layout);
+
+            let stats = heap.stats();
+            let class = stats.get(64).unwrap();
+            assert_eq!(3, class.allocs);
+            assert_eq!(1, class.frees);
+            assert_eq!(2, class.live);
+            assert_eq!(3, class.peak);
+            assert_eq!(2 * (64 - 40), class.fragment);
+            assert_eq!(1, stats.classes().iter().map(|c| c.pages).sum::<usize>());
+
+            // 调整大小只改变浪费的字节数
+            heap.realloc(ptrs[1], layout, 60);
+            assert_eq!(64 - 40 + 64 - 60, heap.stats().get(64).unwrap().fragment);
+            assert_eq!(2, heap.stats().get(64).unwrap().live);
+
+            let report = heap.stats().to_string();
+            assert!(report.starts_with("# name"));
+            assert!(report.contains("size-64"));
+            assert_eq!(heap.stats().classes().len() + 1, report.lines().count());
+        });
+    }
+
+    #[cfg(all(feature = "randomize", not(feature = "debug")))]
+    #[test]
+    fn test_randomize() {
+        with_heap(|heap| unsafe {
+            heap.set_seed(0x1234_5678);
+
+            // 一个slab中的对象全部被分配，但顺序不是按地址递增
+            let layout = Layout::from_size_align(512, 8).unwrap();
+            let mut objs = [0usize; PGSZ / 512];
+            for obj in objs.iter_mut() {
+                *obj = heap.alloc(layout) as usize;
+            }
+            assert!(objs.windows(2).any(|pair| pair[0] > pair[1]));
+
+            let mut sorted = objs;
+            sorted.sort();
+            assert_eq!(0, sorted[0] % PGSZ);
+            assert!(sorted.windows(2).all(|pair| pair[0] + 512 == pair[1]));
+
+            for obj in objs.iter() {
+                heap.dealloc(*obj as *mut u8, layout);
+            }
+        });
+    }
+
+    #[cfg(all(feature = "hardened", not(feature = "debug")))]
+    #[test]
+    #[should_panic(expected = "corrupted")]
+    fn test_hardened_freelist() {
+        with_heap(|heap| unsafe {
+            // 空闲对象中保存的不是下一个对象的原始地址
+            let layout = Layout::from_size_align(128, 8).unwrap();
+            let ptr = heap.alloc(layout) as *mut usize;
+            let next = heap.alloc(layout) as *mut usize;
+            heap.dealloc(next as *mut u8, layout);
+            heap.dealloc(ptr as *mut u8, layout);
+            assert_ne!(next as usize, *ptr);
+
+            // 溢出覆盖空闲对象后，弹出时发现指向slab之外
+            let local = 0usize;
+            *ptr = &local as *const _ as usize;
+            heap.alloc(layout);
+        });
+    }
+
+    #[cfg(feature = "hardened")]
+    #[test]
+    fn test_hardened_default_secret() {
+        // 未设置密钥时使用初始化生成的默认密钥，调试和发布版本行为一致
+        with_zone(|bottom, top| unsafe {
+            let heap: LockedSlab = LockedSlab::new_uninit();
+            heap.init(bottom, top);
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let ptr1 = heap.alloc(layout);
+            let ptr2 = heap.alloc(layout);
+            heap.dealloc(ptr1, layout);
+            heap.dealloc(ptr2, layout);
+            assert!(!heap.alloc(layout).is_null());
+        });
+    }
+
+    #[cfg(feature = "hardened")]
+    #[test]
+    #[should_panic(expected = "corrupted")]
+    fn test_hardened_cache_freelist() {
+        with_heap(|heap| unsafe {
+            let id = heap.create_cache("task", 24, 8, None).unwrap();
+            let obj = heap.cache_alloc(id).unwrap() as *mut usize;
+            let next = heap.cache_alloc(id).unwrap() as *mut usize;
+            heap.cache_free(id, next as *mut u8).unwrap();
+            heap.cache_free(id, obj as *mut u8).unwrap();
+            assert_ne!(next as usize, *obj);
+
+            // 下一个空闲对象不属于该缓存
+            let local = 0usize;
+            *obj = &local as *const _ as usize;
+            heap.cache_alloc(id).unwrap();
+        });
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    fn test_debug_redzone() {
+        with_heap(|heap| unsafe {
+            // 保护区完好时正常释放，释放后对象被毒化
+            let layout = Layout::from_size_align(40, 8).unwrap();
+            let ptr = heap.alloc(layout);
+            assert_eq!(0, ptr as usize % 8);
+            ptr.write_bytes(0, 40);
+            heap.dealloc(ptr, layout);
+            assert_eq!(crate::slab::debug::POISON, *ptr.add(8));
+            assert_eq!(ptr, heap.alloc(layout));
+
+            // 对齐要求大于保护区时仍然满足对齐
+            let layout = Layout::from_size_align(100, 64).unwrap();
+            let ptr = heap.alloc(layout);
+            assert_eq!(0, ptr as usize % 64);
+            heap.dealloc(ptr, layout);
+        });
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    fn test_debug_quarantine() {
+        with_heap(|heap| unsafe {
+            heap.set_quarantine(4);
+
+            // 释放的对象在隔离区中停留，不会被立即重新分配
+            let layout = Layout::from_size_align(40, 8).unwrap();
+            let first = heap.alloc(layout);
+            heap.dealloc(first, layout);
+            let mut objs = [null_mut(); 4];
+            for obj in objs.iter_mut() {
+                *obj = heap.alloc(layout);
+                assert_ne!(first, *obj);
+            }
+
+            // 隔离区满后最早释放的对象离开
+            for obj in objs.iter() {
+                heap.dealloc(*obj, layout);
+            }
+            assert_eq!(first, heap.alloc(layout));
+
+            // 关闭隔离时归还所有对象
+            heap.set_quarantine(0);
+            assert_eq!(objs[3], heap.alloc(layout));
+        });
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    #[should_panic(expected = "UseAfterFree of object")]
+    fn test_debug_quarantine_use_after_free() {
+        with_heap(|heap| unsafe {
+            heap.set_quarantine(1);
+
+            // 对象离开隔离区时发现被写入
+            let layout = Layout::from_size_align(40, 8).unwrap();
+            let ptr = heap.alloc(layout);
+            let other = heap.alloc(layout);
+            heap.dealloc(ptr, layout);
+            *ptr.add(16) = 0;
+            heap.dealloc(other, layout);
+        });
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    #[should_panic(expected = "Overflow of object")]
+    fn test_debug_overflow() {
+        with_heap(|heap| unsafe {
+            let layout = Layout::from_size_align(40, 8).unwrap();
+            let ptr = heap.alloc(layout);
+            *ptr.add(40) = 0;
+            heap.dealloc(ptr, layout);
+        });
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    #[should_panic(expected = "Underflow of object")]
+    fn test_debug_underflow() {
+        with_heap(|heap| unsafe {
+            let layout = Layout::from_size_align(40, 8).unwrap();
+            let ptr = heap.alloc(layout);
+            *ptr.sub(1) = 0;
+            heap.dealloc(ptr, layout);
+        });
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    #[should_panic(expected = "UseAfterFree of object")]
+    fn test_debug_use_after_free() {
+        with_heap(|heap| unsafe {
+            let layout = Layout::from_size_align(40, 8).unwrap();
+            let ptr = heap.alloc(layout);
+            heap.dealloc(ptr, layout);
+            *ptr.add(16) = 0;
+            heap.alloc(layout);
+        });
+    }
+
+    #[test]
+    fn test_reentrant_alloc() {
+        use crate::SlabErr;
+        use core::sync::atomic::{AtomicBool, Ordering};
+
+        // 对象的构造函数在持有内存池锁时执行，在其中分配相当于中断处理程序重入
+        static HEAP: LockedSlab = LockedSlab::new_uninit();
+        static REENTRANT: AtomicBool = AtomicBool::new(false);
+        fn ctor(_: *mut u8) {
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            unsafe {
+                let null = HEAP.alloc(layout).is_null();
+                let err = matches!(HEAP.try_alloc(layout), Err(SlabErr::Reentrant));
+                REENTRANT.store(null && err, Ordering::Relaxed);
+            }
+        }
+
+        // 未设置CPU钩子时无法检测重入，try_alloc 在锁被占用时返回错误
+        static NO_HOOK: LockedSlab = LockedSlab::new_uninit();
+        static WOULD_BLOCK: AtomicBool = AtomicBool::new(false);
+        fn no_hook_ctor(_: *mut u8) {
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let err = unsafe { NO_HOOK.try_alloc(layout) };
+            WOULD_BLOCK.store(matches!(err, Err(SlabErr::WouldBlock)), Ordering::Relaxed);
+        }
+
+        unsafe {
+            let mem = Vec::leak(std::vec![0usize; 4096 * 10]);
+            let bottom = mem.as_ptr() as usize;
+            init_heap(&HEAP, bottom, bottom + mem.len() * 8);
+            HEAP.set_cpu_hook(|| 0);
+            let id = HEAP.create_cache("irq", 24, 8, Some(ctor)).unwrap();
+            HEAP.cache_alloc(id).unwrap();
+            assert!(REENTRANT.load(Ordering::Relaxed));
+
+            // 锁释放后可以正常分配
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            assert!(!HEAP.alloc(layout).is_null());
+            assert!(HEAP.try_alloc(layout).is_ok());
+
+            let mem = Vec::leak(std::vec![0usize; 4096 * 10]);
+            let bottom = mem.as_ptr() as usize;
+            init_heap(&NO_HOOK, bottom, bottom + mem.len() * 8);
+            let id = NO_HOOK
+                .create_cache("irq", 24, 8, Some(no_hook_ctor))
+                .unwrap();
+            NO_HOOK.cache_alloc(id).unwrap();
+            assert!(WOULD_BLOCK.load(Ordering::Relaxed));
+        }
+    }
+
+    #[cfg(feature = "lock_free")]
+    #[test]
+    fn test_lock_free_stress() {
+        use std::thread;
+
+        const THREADS: usize = 8;
+        const ROUNDS: usize = 2000;
+        const OBJS: usize = 16;
+        static HEAP: LockedSlab = LockedSlab::new_uninit();
+
+        let mem = Vec::leak(std::vec![0usize; 4096 * 64]);
+        let bottom = mem.as_ptr() as usize;
+        init_heap(&HEAP, bottom, bottom + mem.len() * 8);
+        HEAP.set_empty_limit(0);
+        let free = HEAP.free_pages();
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|id| {
+                thread::spawn(move || unsafe {
+                    let sizes = [32, 64, 256];
+                    let mut objs = [null_mut::<usize>(); OBJS];
+                    for round in 0..ROUNDS {
+                        let layout = Layout::from_size_align(sizes[round % 3], 8).unwrap();
+                        for obj in objs.iter_mut() {
+                            *obj = HEAP.alloc(layout) as *mut usize;
+                            obj.write_volatile(id);
+                        }
+                        thread::yield_now();
+
+                        // 对象不会同时分配给两个线程
+                        for &obj in objs.iter() {
+                            assert_eq!(id, obj.read_volatile());
+                            HEAP.dealloc(obj as *mut u8, layout);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        HEAP.drain_free_stacks();
+        assert_eq!(free, HEAP.free_pages());
     }
 }
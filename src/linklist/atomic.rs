@@ -0,0 +1,192 @@
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+const OFFSET_BITS: u32 = 40; // 节点相对基址的偏移占用的位数
+const OFFSET_MASK: u64 = (1 << OFFSET_BITS) - 1;
+
+/// 无锁的空闲对象栈(Treiber stack)
+/// 栈顶保存 节点偏移+1 和标记，每次修改栈顶时标记加一，用来避免ABA问题
+/// 节点的第一个字保存下一个节点的 偏移+1，0表示空
+/// 节点地址相对于基址保存，基址由调用者提供，所有节点需要在基址之后 1TiB 以内
+/// 加固模式下节点中保存的next与密钥和节点地址异或，与 Linkedlist 相同
+#[derive(Debug)]
+pub(crate) struct AtomicStack {
+    head: AtomicU64,
+    #[cfg(feature = "hardened")]
+    key: AtomicUsize, // 编码next的密钥
+}
+
+impl AtomicStack {
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicU64::new(0),
+            #[cfg(feature = "hardened")]
+            key: AtomicUsize::new(0),
+        }
+    }
+
+    // 设置编码next的密钥，只能在栈为空时设置
+    #[cfg(feature = "hardened")]
+    pub fn set_key(&self, key: usize) {
+        debug_assert_eq!(0, self.head.load(Ordering::Relaxed) & OFFSET_MASK);
+        self.key.store(key, Ordering::Relaxed);
+    }
+
+    // 节点中保存的next与该值异或
+    #[cfg_attr(not(feature = "hardened"), allow(unused_variables))]
+    fn mask(&self, address: usize) -> usize {
+        #[cfg(feature = "hardened")]
+        return self.key.load(Ordering::Relaxed) ^ address.swap_bytes();
+        #[cfg(not(feature = "hardened"))]
+        0
+    }
+
+    fn pack(offset: u64, tag: u64) -> u64 {
+        (tag << OFFSET_BITS) | offset
+    }
+
+    fn tag(head: u64) -> u64 {
+        (head >> OFFSET_BITS).wrapping_add(1)
+    }
+
+    // 节点的next字段，弹出时节点可能已经被其他线程取走，因此需要原子访问
+    unsafe fn next<'a>(addr: usize) -> &'a AtomicUsize {
+        &*(addr as *const AtomicUsize)
+    }
+
+    /// # Safety
+    /// address需要是基址之后、按usize对齐的可用内存
+    pub unsafe fn push(&self, base: usize, address: usize) {
+        let offset = (address - base + 1) as u64;
+        debug_assert!(offset <= OFFSET_MASK);
+
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let next = (head & OFFSET_MASK) as usize ^ self.mask(address);
+            Self::next(address).store(next, Ordering::Relaxed);
+            match self.head.compare_exchange_weak(
+                head,
+                Self::pack(offset, Self::tag(head)),
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(now) => head = now,
+            }
+        }
+    }
+
+    /// # Safety
+    /// 栈中的节点需要一直可以访问，即使已经被弹出
+    /// 加固模式下解出的next不满足valid且栈顶未变化时说明节点被破坏，在发布到栈顶之前panic
+    #[cfg_attr(not(feature = "hardened"), allow(unused_variables))]
+    pub unsafe fn pop_checked(&self, base: usize, valid: impl Fn(usize) -> bool) -> Option<usize> {
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            let offset = head & OFFSET_MASK;
+            if offset == 0 {
+                return None;
+            }
+
+            let address = base + offset as usize - 1;
+            // 节点可能已被其他线程取走并改写，此时解出的next无效，但标记会使交换失败
+            let next = (Self::next(address).load(Ordering::Relaxed) ^ self.mask(address)) as u64
+                & OFFSET_MASK;
+            #[cfg(feature = "hardened")]
+            if next != 0 && !valid(base + next as usize - 1) {
+                let now = self.head.load(Ordering::Acquire);
+                if now == head {
+                    panic!(
+                        "free list corrupted: {:#x} points to {:#x}",
+                        address,
+                        base + next as usize - 1
+                    );
+                }
+                head = now;
+                continue;
+            }
+            match self.head.compare_exchange_weak(
+                head,
+                Self::pack(next, Self::tag(head)),
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(address),
+                Err(now) => head = now,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::AtomicStack;
+    use std::{sync::Arc, thread, vec::Vec};
+
+    #[test]
+    fn stress_test() {
+        const THREADS: usize = 8;
+        const NODES: usize = 64;
+        const ROUNDS: usize = 10000;
+
+        // 每个节点两个字，第二个字记录持有者
+        let mem: &'static mut [usize] = Vec::leak(std::vec![0usize; NODES * 2]);
+        let base = mem.as_ptr() as usize;
+        let stack = Arc::new(AtomicStack::new());
+        for i in 0..NODES {
+            unsafe { stack.push(base, base + i * 16) };
+        }
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|id| {
+                let stack = stack.clone();
+                thread::spawn(move || {
+                    for _ in 0..ROUNDS {
+                        let node = match unsafe { stack.pop_checked(base, |_| true) } {
+                            Some(node) => node,
+                            None => continue,
+                        };
+
+                        // 同一节点不会同时被两个线程持有
+                        let owner = (node + 8) as *mut usize;
+                        unsafe {
+                            assert_eq!(0, owner.read_volatile());
+                            owner.write_volatile(id + 1);
+                            thread::yield_now();
+                            assert_eq!(id + 1, owner.read_volatile());
+                            owner.write_volatile(0);
+                            stack.push(base, node);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut counts = 0;
+        while unsafe { stack.pop_checked(base, |_| true) }.is_some() {
+            counts += 1;
+        }
+        assert_eq!(NODES, counts);
+    }
+
+    // 加固模式下被改写的next在弹出前被发现
+    #[cfg(feature = "hardened")]
+    #[test]
+    #[should_panic(expected = "free list corrupted")]
+    fn corrupt_test() {
+        let mem = [0usize; 4];
+        let base = mem.as_ptr() as usize;
+        let stack = AtomicStack::new();
+        stack.set_key(0x5eed);
+        unsafe {
+            stack.push(base, base);
+            stack.push(base, base + 16);
+            (base as *mut usize).add(2).write(0x1000);
+            stack.pop_checked(base, |addr| addr == base || addr == base + 16);
+        }
+    }
+}
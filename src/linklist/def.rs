@@ -8,7 +8,6 @@
 // pub(crate) const POOL_32: usize = 7;
 // pub(crate) const POOL_PGSZ: usize = 8;
 
-pub(crate) const POOL_COUNT: usize = 9;
 pub(crate) const POOL_SIZE_4096: usize = 4096;
 pub(crate) const POOL_SIZE_2048: usize = 2048;
 pub(crate) const POOL_SIZE_1024: usize = 1024;
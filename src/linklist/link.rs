@@ -1,35 +1,37 @@
-use crate::{align_down, align_up, is_align};
+use crate::{align_down, align_up};
 
 use super::node::Node;
+#[cfg(feature = "randomize")]
+use crate::rand::Rng;
 use core::ptr::null_mut;
-use xxos_log::info;
 
-#[derive(Clone, Copy)]
+/// 加固模式下节点中保存的next与密钥和节点地址异或，
+/// 溢出写入的地址不经过编码无法被解出
+#[derive(Debug, Clone, Copy)]
 pub(crate) struct Linkedlist {
     head: *mut Node,
     tail: *mut Node,
+    #[cfg(feature = "hardened")]
+    key: usize, // 编码next的密钥
 }
 
-pub struct LinkedlistIter {
+#[allow(unused)]
+pub struct LinkedlistIter<'a> {
+    list: &'a Linkedlist,
     current: *mut Node,
 }
 
-impl Iterator for LinkedlistIter {
+impl Iterator for LinkedlistIter<'_> {
     type Item = *mut Node;
 
     fn next(&mut self) -> Option<Self::Item> {
-        unsafe {
-            let head = self.current;
-
-            if (*head).next.is_null() {
-                None
-            } else {
-                let item = self.current;
-                let next = (*item).next;
-                self.current = next;
-                Some(item)
-            }
+        if self.current.is_null() {
+            return None;
         }
+
+        let item = self.current;
+        self.current = unsafe { self.list.next_of(item) };
+        Some(item)
     }
 }
 
@@ -39,53 +41,141 @@ impl Linkedlist {
         Self {
             head: null_mut(),
             tail: null_mut(),
+            #[cfg(feature = "hardened")]
+            key: 0,
         }
     }
 
-    pub fn iter(&self) -> LinkedlistIter {
-        LinkedlistIter { current: self.head }
+    // 设置编码next的密钥，只能在链表为空时设置
+    #[cfg(feature = "hardened")]
+    pub fn set_key(&mut self, key: usize) {
+        debug_assert!(self.is_empty());
+        self.key = key;
+    }
+
+    // 节点地址按字节反转后参与编码，使相邻节点的编码差异更大
+    #[cfg(feature = "hardened")]
+    fn mask(&self, node: *mut Node) -> usize {
+        self.key ^ (node as usize).swap_bytes()
+    }
+
+    // 读出节点的next
+    #[inline]
+    unsafe fn next_of(&self, node: *mut Node) -> *mut Node {
+        #[cfg(feature = "hardened")]
+        return ((*node).next as usize ^ self.mask(node)) as *mut Node;
+        #[cfg(not(feature = "hardened"))]
+        (*node).next
+    }
+
+    #[inline]
+    unsafe fn set_next(&self, node: *mut Node, next: *mut Node) {
+        #[cfg(feature = "hardened")]
+        let next = (next as usize ^ self.mask(node)) as *mut Node;
+        (*node).next = next;
+    }
+
+    #[allow(unused)]
+    pub fn iter(&self) -> LinkedlistIter<'_> {
+        LinkedlistIter {
+            list: self,
+            current: self.head,
+        }
     }
 
     pub fn is_empty(&self) -> bool {
         self.head.is_null()
     }
 
+    // 清空链表，加固模式下保留密钥
+    pub fn clear(&mut self) {
+        self.head = null_mut();
+        self.tail = null_mut();
+    }
+
+    // 地址是否为头节点，刚释放的对象位于头部
+    pub fn is_head(&self, address: usize) -> bool {
+        !self.is_empty() && self.head as usize == address
+    }
+
+    #[allow(unused)]
     pub fn len(&self) -> usize {
-        let mut len = 1;
-        if self.is_empty() {
+        self.iter().count()
+    }
+
+    // 将start到end之间的内存按chunk_size切分放入链表，返回块数
+    // 起始地址按align对齐，块的对齐不超过align，调用者需保证chunk_size是align的倍数
+    #[cfg_attr(feature = "randomize", allow(unused))]
+    pub unsafe fn init(
+        &mut self,
+        start: usize,
+        end: usize,
+        chunk_size: usize,
+        align: usize,
+    ) -> usize {
+        let start = align_up!(start, align);
+        let end = align_down!(end, align);
+        self.head = null_mut();
+        self.tail = null_mut();
+        // 倒序压入，使链表按地址递增
+        let counts = (end - start) / chunk_size;
+        for i in (0..counts).rev() {
+            self.push(start + i * chunk_size);
+        }
+        counts
+    }
+    // 与 init 相同，但链表中块的顺序随机
+    // 先用Sattolo算法在块中生成随机的单个环，每个块保存环上下一个块的序号，
+    // 再从随机的块开始沿环连接，不需要额外内存
+    #[cfg(feature = "randomize")]
+    pub unsafe fn init_shuffled(
+        &mut self,
+        start: usize,
+        end: usize,
+        chunk_size: usize,
+        align: usize,
+        rng: &mut Rng,
+    ) -> usize {
+        let start = align_up!(start, align);
+        let end = align_down!(end, align);
+        self.head = null_mut();
+        self.tail = null_mut();
+
+        let counts = (end - start) / chunk_size;
+        if counts == 0 {
             return 0;
         }
-        for _ in self.iter() {
-            len += 1;
+        let slot = |i: usize| (start + i * chunk_size) as *mut usize;
+        for i in 0..counts {
+            *slot(i) = i;
         }
-        len
-    }
-
-    pub unsafe fn init(&mut self, start: usize, end: usize, chunk_size: usize) {
-        let start = align_up!(start, chunk_size);
-        info!("the satrt is {:#x}", start);
-        let end = align_down!(end, chunk_size);
-        info!("the end is {:#x}", end);
-        self.tail = Node::to_mut_node_ptr(start);
-        for address in (start..end).step_by(chunk_size) {
-            let head = Node::to_mut_node_ptr(address);
-            if address + chunk_size == end {
-                unsafe { (*head).next = null_mut() }
-                break;
-            }
-            let next = Node::to_mut_node_ptr(address + chunk_size);
-            unsafe {
-                (*head).next = next;
-            }
+        for i in (1..counts).rev() {
+            core::ptr::swap(slot(i), slot(rng.below(i)));
+        }
+
+        // 环上first的前一个块作为尾
+        let first = rng.below(counts);
+        let mut node = first;
+        for _ in 0..counts {
+            let next = *slot(node);
+            let next_node = if next == first {
+                self.tail = Node::to_mut_node_ptr(slot(node) as usize);
+                null_mut()
+            } else {
+                Node::to_mut_node_ptr(slot(next) as usize)
+            };
+            self.set_next(Node::to_mut_node_ptr(slot(node) as usize), next_node);
+            node = next;
         }
-        self.head = Node::to_mut_node_ptr(start);
-        info!("init ok the len is {}", self.len())
+        self.head = Node::to_mut_node_ptr(slot(first) as usize);
+        counts
     }
+
     //pop head
     pub unsafe fn pop<T>(&mut self) -> Option<*mut T> {
         if !self.head.is_null() {
             let head = self.head;
-            self.head = (*head).next;
+            self.head = self.next_of(head);
 
             if self.is_empty() {
                 self.tail = null_mut();
@@ -96,42 +186,36 @@ impl Linkedlist {
             None
         }
     }
-    //pop head
-    pub unsafe fn pop_algin<T>(&mut self, algin: usize) -> Option<*mut T> {
-        let tail_now = self.tail as usize;
-        let ptr = loop {
-            let Some(p) = self.pop() else {
-                break None;
-            };
-            if p as usize == tail_now && !is_align!(tail_now, algin) {
-                break None;
-            };
-            if is_align!(p as usize, algin) {
-                break Some(p);
-            } else {
-                self.push_tail(p as usize);
-            }
-        };
-        if self.is_empty() {
-            self.tail = null_mut();
-        }
-        ptr
-    }
     //push head
     pub unsafe fn push(&mut self, address: usize) {
         let head = Node::to_mut_node_ptr(address);
         assert!(!head.is_null());
-        (*head).next = self.head;
+        if self.is_empty() {
+            self.tail = head;
+        }
+        self.set_next(head, self.head);
         self.head = head;
     }
 
-    pub unsafe fn push_tail(&mut self, address: usize) {
-        let tail = self.tail;
-        let new = Node::to_mut_node_ptr(address);
-        assert!(!new.is_null());
-        (*tail).next = new;
-        (*new).next = null_mut();
-        self.tail = new;
+    // 弹出头节点，加固模式下检查新的头节点在start到end之间，否则说明链表被破坏
+    pub unsafe fn pop_within<T>(&mut self, start: usize, end: usize) -> Option<*mut T> {
+        self.pop_checked(|next| (start..end).contains(&next))
+    }
+
+    // 弹出头节点，加固模式下检查新的头节点满足valid，否则说明链表被破坏
+    #[cfg_attr(not(feature = "hardened"), allow(unused_variables))]
+    pub unsafe fn pop_checked<T>(&mut self, valid: impl Fn(usize) -> bool) -> Option<*mut T> {
+        #[cfg(feature = "hardened")]
+        if !self.head.is_null() {
+            let next = self.next_of(self.head) as usize;
+            if next != 0 && !valid(next) {
+                panic!(
+                    "free list corrupted: {:#x} points to {:#x}",
+                    self.head as usize, next
+                );
+            }
+        }
+        self.pop()
     }
 }
 
@@ -171,7 +255,7 @@ mod tests {
                 (end - start) / 64
             );
 
-            linked_list.init(start, end, POOL_SIZE_64);
+            linked_list.init(start, end, POOL_SIZE_64, POOL_SIZE_64);
 
             assert_eq!((end - start) / 64, linked_list.len());
 
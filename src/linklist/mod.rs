@@ -1,3 +1,5 @@
+#[cfg(feature = "lock_free")]
+pub(crate) mod atomic;
 pub mod def;
 pub mod link;
 pub mod node;
@@ -0,0 +1,268 @@
+use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+/// 分配器使用的锁
+/// # Safety
+/// lock 返回后直到 unlock 之前，其他调用者的 lock 都不能返回
+pub unsafe trait RawLock {
+    const INIT: Self; // 未加锁的初始状态
+
+    fn lock(&self);
+
+    // 锁已被占用时立即返回false
+    fn try_lock(&self) -> bool;
+
+    /// # Safety
+    /// 调用者需要持有锁
+    unsafe fn unlock(&self);
+}
+
+/// 自旋锁
+#[derive(Debug)]
+pub struct SpinLock(AtomicBool);
+
+unsafe impl RawLock for SpinLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = SpinLock(AtomicBool::new(false));
+
+    fn lock(&self) {
+        while !self.try_lock() {
+            while self.0.load(Ordering::Relaxed) {
+                spin_loop();
+            }
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.0
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// 排队自旋锁，按请求顺序获得锁
+#[derive(Debug)]
+pub struct TicketLock {
+    next: AtomicUsize,    // 下一个发出的号
+    serving: AtomicUsize, // 当前持有锁的号
+}
+
+unsafe impl RawLock for TicketLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = TicketLock {
+        next: AtomicUsize::new(0),
+        serving: AtomicUsize::new(0),
+    };
+
+    fn lock(&self) {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.serving.load(Ordering::Acquire) != ticket {
+            spin_loop();
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        let ticket = self.serving.load(Ordering::Relaxed);
+        self.next
+            .compare_exchange(ticket, ticket + 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        self.serving.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// 开关中断的钩子，由使用者根据目标平台提供
+pub trait IrqHooks {
+    // 关闭中断，返回关闭前中断是否打开
+    fn disable() -> bool;
+
+    // 恢复到关闭前的状态
+    fn restore(enabled: bool);
+}
+
+/// 持有期间关闭中断的自旋锁，避免中断处理程序中分配时死锁
+pub struct IrqLock<H: IrqHooks> {
+    lock: SpinLock,
+    enabled: AtomicBool, // 加锁前中断是否打开
+    hooks: PhantomData<fn() -> H>,
+}
+
+unsafe impl<H: IrqHooks> RawLock for IrqLock<H> {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = IrqLock {
+        lock: SpinLock::INIT,
+        enabled: AtomicBool::new(false),
+        hooks: PhantomData,
+    };
+
+    fn lock(&self) {
+        let enabled = H::disable();
+        self.lock.lock();
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    fn try_lock(&self) -> bool {
+        let enabled = H::disable();
+        if self.lock.try_lock() {
+            self.enabled.store(enabled, Ordering::Relaxed);
+            true
+        } else {
+            H::restore(enabled);
+            false
+        }
+    }
+
+    unsafe fn unlock(&self) {
+        let enabled = self.enabled.load(Ordering::Relaxed);
+        self.lock.unlock();
+        H::restore(enabled);
+    }
+}
+
+/// 由 RawLock 保护的数据
+pub struct Lock<L: RawLock, T> {
+    raw: L,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<L: RawLock + Sync, T: Send> Sync for Lock<L, T> {}
+unsafe impl<L: RawLock + Send, T: Send> Send for Lock<L, T> {}
+
+impl<L: RawLock, T> Lock<L, T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            raw: L::INIT,
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> LockGuard<'_, L, T> {
+        self.raw.lock();
+        LockGuard {
+            lock: self,
+            cpu: PhantomData,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<LockGuard<'_, L, T>> {
+        if self.raw.try_lock() {
+            Some(LockGuard {
+                lock: self,
+                cpu: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// 持有锁期间的守卫，析构时释放锁
+/// 守卫不能发送到其他线程(CPU)：IrqLock 在加锁的CPU上关闭中断，只能在同一CPU上恢复
+/// ```compile_fail
+/// fn send<T: Send>(_: T) {}
+/// let lock: xxos_alloc::Lock<xxos_alloc::SpinLock, usize> = xxos_alloc::Lock::new(0);
+/// send(lock.lock());
+/// ```
+pub struct LockGuard<'a, L: RawLock, T> {
+    lock: &'a Lock<L, T>,
+    cpu: PhantomData<*const ()>, // 使守卫不是Send
+}
+
+unsafe impl<L: RawLock + Sync, T: Sync> Sync for LockGuard<'_, L, T> {}
+
+impl<L: RawLock, T> Deref for LockGuard<'_, L, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<L: RawLock, T> DerefMut for LockGuard<'_, L, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<L: RawLock, T> Drop for LockGuard<'_, L, T> {
+    fn drop(&mut self) {
+        unsafe { self.lock.raw.unlock() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::{IrqHooks, IrqLock, Lock, RawLock, SpinLock, TicketLock};
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use std::{sync::Arc, thread, vec::Vec};
+
+    fn counter_test<L: RawLock + Send + Sync + 'static>() {
+        // 排队锁在线程数多于CPU数时每次交接都要等待调度，轮数不宜过多
+        const THREADS: usize = 4;
+        const ROUNDS: usize = 1000;
+
+        let counter = Arc::new(Lock::<L, usize>::new(0));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let counter = counter.clone();
+                thread::spawn(move || {
+                    for _ in 0..ROUNDS {
+                        *counter.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(THREADS * ROUNDS, *counter.lock());
+    }
+
+    #[test]
+    fn lock_test() {
+        counter_test::<SpinLock>();
+        counter_test::<TicketLock>();
+
+        let lock = Lock::<TicketLock, usize>::new(0);
+        let guard = lock.lock();
+        assert!(lock.try_lock().is_none());
+        drop(guard);
+        assert!(lock.try_lock().is_some());
+    }
+
+    #[test]
+    fn irq_lock_test() {
+        static ENABLED: AtomicBool = AtomicBool::new(true);
+        struct Hooks;
+        impl IrqHooks for Hooks {
+            fn disable() -> bool {
+                ENABLED.swap(false, Ordering::Relaxed)
+            }
+            fn restore(enabled: bool) {
+                ENABLED.store(enabled, Ordering::Relaxed);
+            }
+        }
+
+        // 持有锁时中断关闭，释放后恢复
+        let lock = Lock::<IrqLock<Hooks>, usize>::new(0);
+        let guard = lock.lock();
+        assert!(!ENABLED.load(Ordering::Relaxed));
+        assert!(lock.try_lock().is_none());
+        assert!(!ENABLED.load(Ordering::Relaxed));
+        drop(guard);
+        assert!(ENABLED.load(Ordering::Relaxed));
+    }
+}
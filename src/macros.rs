@@ -33,6 +33,25 @@ macro_rules! align_up {
     };
 }
 
+// 发出追踪事件，未启用 trace 特性时事件不会被构造
+#[cfg(feature = "trace")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! trace_event {
+    ($event:expr) => {
+        $crate::trace::emit($event)
+    };
+}
+
+#[cfg(not(feature = "trace"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! trace_event {
+    ($event:expr) => {{
+        let _ = || $event;
+    }};
+}
+
 #[cfg(test)]
 pub mod macro_test {
     extern crate std;
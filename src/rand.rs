@@ -0,0 +1,67 @@
+/// 打散数值的各位(splitmix64的输出函数)，用于由地址或种子生成密钥
+pub(crate) fn mix(value: u64) -> u64 {
+    let mut x = value;
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^= x >> 33;
+    x
+}
+
+/// 由种子生成的伪随机数(xorshift64*)，只用于打乱分配顺序，不是密码学安全的
+#[cfg(feature = "randomize")]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+#[cfg(feature = "randomize")]
+impl Rng {
+    pub const fn new() -> Self {
+        Self {
+            state: 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        // 状态不能为0
+        self.state = mix(seed) | 1;
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // 0到n之间(不含n)的随机数
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[cfg(all(test, feature = "randomize"))]
+mod tests {
+    use super::Rng;
+
+    #[test]
+    fn below_test() {
+        let mut rng = Rng::new();
+        rng.seed(42);
+        let mut seen = [false; 8];
+        for _ in 0..1000 {
+            let value = rng.below(8);
+            assert!(value < 8);
+            seen[value] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+
+        // 相同的种子产生相同的序列
+        let mut other = Rng::new();
+        other.seed(42);
+        rng.seed(42);
+        assert_eq!(rng.next_u64(), other.next_u64());
+    }
+}
@@ -0,0 +1,261 @@
+use super::{
+    def::SlabErr,
+    page::{Owner, PageKind, PageTable},
+};
+use crate::{align_up, linklist::link::Linkedlist, trace::Event, trace_event, BuddyAllocator};
+use core::{
+    alloc::Layout,
+    mem::{align_of, size_of},
+};
+
+/// 命名缓存的编号，由 create_cache 返回
+/// 缓存槽被销毁后重新使用时代数加一，旧的编号不再有效
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheId {
+    pub(crate) index: usize,
+    pub(crate) gen: usize,
+}
+
+/// 对象构造函数，在slab页建立时对页中的每个对象调用一次
+pub type Ctor = fn(*mut u8);
+
+/// 命名对象缓存(kmem_cache)
+/// 每个缓存只分配一种大小的对象，拥有自己的slab页
+/// 每个slab页的第一个字保存下一个slab页的地址，页描述符记录slab属于哪个缓存
+/// 有构造函数时，空闲链表的指针保存在对象之后，不会破坏已构造的对象
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ObjCache {
+    pub(crate) name: Option<&'static str>,
+    align: usize,       // 对象对齐
+    slot: usize,        // 每个对象实际占用的大小
+    link_offset: usize, // 空闲链表指针在对象中的偏移
+    slab_size: usize,   // 每个slab的大小
+    ctor: Option<Ctor>,
+    free: Linkedlist,  // 空闲对象(保存的是指针所在地址)
+    pages: Linkedlist, // 所有slab页
+    pub(crate) inuse: usize,
+    pub(crate) index: usize, // 缓存槽的序号
+    pub(crate) gen: usize,   // 缓存槽的代数，销毁后保留
+}
+
+impl ObjCache {
+    pub const fn new() -> Self {
+        Self {
+            name: None,
+            align: 0,
+            slot: 0,
+            link_offset: 0,
+            slab_size: 0,
+            ctor: None,
+            free: Linkedlist::new(),
+            pages: Linkedlist::new(),
+            inuse: 0,
+            index: 0,
+            gen: 0,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.name.is_some()
+    }
+
+    // 设置编码空闲链表的密钥，在缓存创建后、分配对象前设置
+    #[cfg(feature = "hardened")]
+    pub fn set_key(&mut self, key: usize) {
+        self.free.set_key(key);
+    }
+
+    pub fn create(
+        name: &'static str,
+        size: usize,
+        align: usize,
+        ctor: Option<Ctor>,
+        pgsz: usize,
+    ) -> Result<Self, SlabErr> {
+        if size == 0 {
+            return Err(SlabErr::WrongSize);
+        }
+        if !align.is_power_of_two() || align > pgsz {
+            return Err(SlabErr::WrongAlign);
+        }
+
+        let align = core::cmp::max(align, align_of::<usize>());
+        let link_offset = match ctor {
+            Some(_) => align_up!(size, align_of::<usize>()),
+            None => 0,
+        };
+        let slot = align_up!(
+            core::cmp::max(size, link_offset + size_of::<usize>()),
+            align
+        );
+        let header = align_up!(size_of::<usize>(), align);
+        let slab_size = core::cmp::max(pgsz, (header + slot).next_power_of_two());
+
+        Ok(Self {
+            name: Some(name),
+            align,
+            slot,
+            link_offset,
+            slab_size,
+            ctor,
+            free: Linkedlist::new(),
+            pages: Linkedlist::new(),
+            inuse: 0,
+            index: 0,
+            gen: 0,
+        })
+    }
+
+    // 从页内存分配器取一个slab，构造其中的对象并放入空闲链表
+    unsafe fn grow<const PGSZ: usize, const MAX_ORDER: usize>(
+        &mut self,
+        buddy: &mut BuddyAllocator<PGSZ, MAX_ORDER>,
+        pages: &PageTable<PGSZ>,
+    ) -> Result<(), SlabErr> {
+        let layout =
+            Layout::from_size_align(self.slab_size, PGSZ).map_err(|_| SlabErr::WrongSize)?;
+        let page = buddy.allocate(layout)?;
+        trace_event!(Event::CacheGrow {
+            name: self.name.unwrap_or_default(),
+            start: page
+        });
+
+        self.pages.push(page);
+        self.set_kind(pages, page, true);
+
+        let start = self.first_obj(page);
+        let end = page + self.slab_size;
+        let mut obj = start;
+        while obj + self.slot <= end {
+            if let Some(ctor) = self.ctor {
+                ctor(obj as *mut u8);
+            }
+            self.free.push(obj + self.link_offset);
+            obj += self.slot;
+        }
+
+        Ok(())
+    }
+
+    pub unsafe fn allocate<const PGSZ: usize, const MAX_ORDER: usize>(
+        &mut self,
+        buddy: &mut BuddyAllocator<PGSZ, MAX_ORDER>,
+        pages: &PageTable<PGSZ>,
+    ) -> Result<*mut u8, SlabErr> {
+        if self.free.is_empty() {
+            self.grow(buddy, pages)?;
+        }
+
+        // 加固模式下检查下一个空闲对象属于该缓存
+        let mut free = self.free;
+        let link = free
+            .pop_checked::<u8>(|next| self.slab_of(pages, next - self.link_offset).is_some())
+            .ok_or(SlabErr::NotFound)?;
+        self.free = free;
+        self.inuse += 1;
+        let obj = link as usize - self.link_offset;
+        self.mark(pages, obj, true);
+        Ok(obj as *mut u8)
+    }
+
+    // slab中第一个对象的地址，slab的第一个字保存链表指针
+    fn first_obj(&self, page: usize) -> usize {
+        align_up!(page + size_of::<usize>(), self.align)
+    }
+
+    // 由页描述符找到对象所在的slab，对象需要属于该缓存且是对象的起始地址
+    fn slab_of<const PGSZ: usize>(&self, pages: &PageTable<PGSZ>, addr: usize) -> Option<usize> {
+        match pages.owner(addr)? {
+            Owner::Cache { index, start } if index == self.index => {
+                let first = self.first_obj(start);
+                (addr >= first
+                    && (addr - first).is_multiple_of(self.slot)
+                    && addr + self.slot <= start + self.slab_size)
+                    .then_some(start)
+            }
+            _ => None,
+        }
+    }
+
+    // 设置对象的使用标记，返回原来的标记
+    fn mark<const PGSZ: usize>(&self, pages: &PageTable<PGSZ>, addr: usize, used: bool) -> bool {
+        match self.slab_of(pages, addr) {
+            Some(start) => pages.mark(start, (addr - self.first_obj(start)) / self.slot, used),
+            None => !used,
+        }
+    }
+
+    // 在页描述符中登记或清除slab
+    unsafe fn set_kind<const PGSZ: usize>(&self, pages: &PageTable<PGSZ>, page: usize, used: bool) {
+        let desc = pages.get(page).expect("cache slab is out of the zone");
+        (*desc).kind = match used {
+            true => PageKind::Cache(self.index as u32),
+            false => PageKind::Other,
+        };
+        for i in 1..self.slab_size / PGSZ {
+            (*desc.add(i)).kind = match used {
+                true => PageKind::Tail(i as u32),
+                false => PageKind::Other,
+            };
+        }
+    }
+
+    // 对象不属于该缓存时返回 InvalidFree，没有使用标记时说明重复释放
+    pub unsafe fn deallocate<const PGSZ: usize>(
+        &mut self,
+        pages: &PageTable<PGSZ>,
+        ptr: *mut u8,
+    ) -> Result<(), SlabErr> {
+        if self.slab_of(pages, ptr as usize).is_none() {
+            return Err(SlabErr::InvalidFree);
+        } else if !self.mark(pages, ptr as usize, false) {
+            return Err(SlabErr::DoubleFree);
+        }
+        self.free.push(ptr as usize + self.link_offset);
+        self.inuse -= 1;
+        Ok(())
+    }
+
+    // 没有正在使用的对象时归还所有slab页，缓存仍然可用，返回归还的页数
+    pub unsafe fn shrink<const PGSZ: usize, const MAX_ORDER: usize>(
+        &mut self,
+        buddy: &mut BuddyAllocator<PGSZ, MAX_ORDER>,
+        table: &PageTable<PGSZ>,
+    ) -> usize {
+        if self.inuse != 0 {
+            return 0;
+        }
+
+        let mut pages = 0;
+        while let Some(page) = self.pages.pop::<u8>() {
+            self.set_kind(table, page as usize, false);
+            if buddy.deallocate(page as usize, self.slab_size).is_ok() {
+                pages += self.slab_size / PGSZ;
+            }
+        }
+        self.free.clear();
+        pages
+    }
+
+    // 归还所有slab页，缓存中不能有正在使用的对象
+    pub unsafe fn destroy<const PGSZ: usize, const MAX_ORDER: usize>(
+        &mut self,
+        buddy: &mut BuddyAllocator<PGSZ, MAX_ORDER>,
+        pages: &PageTable<PGSZ>,
+    ) -> Result<(), SlabErr> {
+        if self.inuse != 0 {
+            return Err(SlabErr::Busy);
+        }
+
+        while let Some(page) = self.pages.pop::<u8>() {
+            self.set_kind(pages, page as usize, false);
+            buddy.deallocate(page as usize, self.slab_size)?;
+        }
+        *self = Self {
+            gen: self.gen,
+            ..Self::new()
+        };
+
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+use super::def::MAX_CLASSES;
+use crate::linklist::def::*;
+use core::alloc::Layout;
+
+const SMALL_GRANULE: usize = 8; // 小对象查找表的粒度
+const SMALL_MAX: usize = 1024; // 小对象查找表覆盖的最大大小
+const LARGE_GRANULE: usize = 128; // 大对象查找表的粒度
+const NONE: u8 = u8::MAX;
+
+pub const MAX_CLASS_SIZE: usize = 32768; // 大小类别的最大值
+
+/// 默认的大小类别，原来的8个内存池之后是使用多页slab的8192到32768
+pub const POW2_CLASSES: [usize; 11] = [
+    POOL_SIZE_32,
+    POOL_SIZE_64,
+    POOL_SIZE_128,
+    POOL_SIZE_256,
+    POOL_SIZE_512,
+    POOL_SIZE_1024,
+    POOL_SIZE_2048,
+    POOL_SIZE_4096,
+    8192,
+    16384,
+    MAX_CLASS_SIZE,
+];
+
+/// 更细的大小类别，4096以下在2的幂之间插入 1.5 倍的类别，
+/// 4096到32768之间每个2的幂区间再分为4份，这些类别使用多页的slab
+pub const FINE_CLASSES: [usize; 30] = [
+    8, 16, 24, 32, 48, 64, 96, 128, 192, 256, 384, 512, 768, 1024, 1536, 2048, 3072, 4096, 5120,
+    6144, 7168, 8192, 10240, 12288, 14336, 16384, 20480, 24576, 28672, 32768,
+];
+
+/// 大小类别表
+/// 类别需要严格递增且为8的倍数，最小为8，大于1024的类别需要为128的倍数
+/// 构造时生成两级查找表，根据大小查找类别为O(1)
+#[derive(Debug, Clone, Copy)]
+pub struct SizeClasses {
+    sizes: [usize; MAX_CLASSES],
+    len: usize,
+    small: [u8; SMALL_MAX / SMALL_GRANULE],
+    large: [u8; MAX_CLASS_SIZE / LARGE_GRANULE],
+}
+
+impl SizeClasses {
+    pub const fn new(classes: &[usize]) -> Self {
+        assert!(
+            !classes.is_empty() && classes.len() <= MAX_CLASSES,
+            "wrong size class counts"
+        );
+
+        let mut sizes = [0; MAX_CLASSES];
+        let mut i = 0;
+        while i < classes.len() {
+            let size = classes[i];
+            // 空闲对象中需要保存链表的指针
+            assert!(size >= SMALL_GRANULE, "size class is too small");
+            assert!(
+                size & (SMALL_GRANULE - 1) == 0,
+                "size class must be 8 aligned"
+            );
+            assert!(size <= MAX_CLASS_SIZE, "size class is too big");
+            assert!(
+                size <= SMALL_MAX || size & (LARGE_GRANULE - 1) == 0,
+                "size class above 1024 must be 128 aligned"
+            );
+            assert!(
+                i == 0 || size > classes[i - 1],
+                "size classes must increase"
+            );
+            sizes[i] = size;
+            i += 1;
+        }
+
+        Self {
+            sizes,
+            len: classes.len(),
+            small: Self::build::<{ SMALL_MAX / SMALL_GRANULE }>(classes, SMALL_GRANULE),
+            large: Self::build::<{ MAX_CLASS_SIZE / LARGE_GRANULE }>(classes, LARGE_GRANULE),
+        }
+    }
+
+    // 查找表的第i项为不小于 (i + 1) * granule 的最小类别
+    const fn build<const N: usize>(classes: &[usize], granule: usize) -> [u8; N] {
+        let mut table = [NONE; N];
+        let mut class = 0;
+        let mut i = 0;
+        while i < N {
+            while class < classes.len() && classes[class] < (i + 1) * granule {
+                class += 1;
+            }
+            if class < classes.len() {
+                table[i] = class as u8;
+            }
+            i += 1;
+        }
+        table
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // 第index个类别的大小
+    pub fn size(&self, index: usize) -> usize {
+        self.sizes[index]
+    }
+
+    // 类别的自然对齐，即类别大小中最大的2的幂因子
+    pub fn align(&self, index: usize) -> usize {
+        let size = self.sizes[index];
+        size & size.wrapping_neg()
+    }
+
+    // 对象实际保证的对齐，slab的起始地址只按页对齐，因此不超过页大小
+    pub fn slab_align(&self, index: usize, pgsz: usize) -> usize {
+        core::cmp::min(self.align(index), pgsz)
+    }
+
+    // slab中地址所在对象的起始地址，对象从slab的start处开始连续存放
+    pub(crate) fn obj_start(&self, index: usize, start: usize, addr: usize) -> usize {
+        let size = self.sizes[index];
+        start + (addr - start) / size * size
+    }
+
+    // 最大的类别
+    pub fn max_size(&self) -> usize {
+        self.sizes[self.len - 1]
+    }
+
+    // 能容纳size的最小类别
+    pub fn index(&self, size: usize) -> Option<usize> {
+        let class = match size {
+            0 => NONE,
+            1..=SMALL_MAX => self.small[(size - 1) / SMALL_GRANULE],
+            _ if size <= MAX_CLASS_SIZE => self.large[(size - 1) / LARGE_GRANULE],
+            _ => NONE,
+        };
+
+        if class == NONE {
+            None
+        } else {
+            Some(class as usize)
+        }
+    }
+
+    // 能容纳size且自然对齐不小于align的最小类别
+    pub fn index_aligned(&self, size: usize, align: usize) -> Option<usize> {
+        let mut index = self.index(core::cmp::max(size, align))?;
+        while self.align(index) < align {
+            index += 1;
+            if index == self.len {
+                return None;
+            }
+        }
+        Some(index)
+    }
+
+    // 能满足layout的类别，对齐超过max_align时没有合适的类别
+    pub fn index_layout(&self, layout: Layout, max_align: usize) -> Option<usize> {
+        if layout.size() == 0 || layout.align() > max_align {
+            return None;
+        }
+        self.index_aligned(layout.size(), layout.align())
+    }
+}
+
+impl Default for SizeClasses {
+    fn default() -> Self {
+        Self::new(&POW2_CLASSES)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{SizeClasses, FINE_CLASSES, POW2_CLASSES};
+
+    #[test]
+    #[should_panic(expected = "too small")]
+    fn zero_class_test() {
+        SizeClasses::new(&[0, 8]);
+    }
+
+    #[test]
+    fn index_test() {
+        let classes = SizeClasses::new(&POW2_CLASSES);
+        assert_eq!(Some(0), classes.index(1));
+        assert_eq!(Some(0), classes.index(32));
+        assert_eq!(Some(1), classes.index(33));
+        assert_eq!(Some(4), classes.index(290));
+        assert_eq!(Some(7), classes.index(4096));
+        assert_eq!(Some(8), classes.index(4097));
+        assert_eq!(Some(10), classes.index(32768));
+        assert_eq!(None, classes.index(32769));
+        assert_eq!(None, classes.index(0));
+
+        let classes = SizeClasses::new(&FINE_CLASSES);
+        assert_eq!(8, classes.size(classes.index(8).unwrap()));
+        assert_eq!(24, classes.size(classes.index(17).unwrap()));
+        assert_eq!(192, classes.size(classes.index(130).unwrap()));
+        assert_eq!(1536, classes.size(classes.index(1025).unwrap()));
+        assert_eq!(3072, classes.size(classes.index(3000).unwrap()));
+
+        // 48的自然对齐为16，需要64对齐时使用64的类别
+        assert_eq!(16, classes.align(classes.index(48).unwrap()));
+        assert_eq!(64, classes.size(classes.index_aligned(48, 64).unwrap()));
+        assert_eq!(32, classes.size(classes.index_aligned(24, 16).unwrap()));
+        assert_eq!(
+            4096,
+            classes.size(classes.index_aligned(3000, 4096).unwrap())
+        );
+        assert_eq!(None, classes.index_aligned(8, 65536));
+
+        // 超过4096的类别
+        assert_eq!(6144, classes.size(classes.index(6000).unwrap()));
+        assert_eq!(32768, classes.size(classes.index(30000).unwrap()));
+        assert_eq!(None, classes.index(32769));
+        assert_eq!(
+            4096,
+            classes.slab_align(classes.index(20480).unwrap(), 4096)
+        );
+
+        // 与线性查找的结果一致
+        for size in 1..=32768 {
+            let expect = FINE_CLASSES.iter().position(|&class| class >= size);
+            assert_eq!(expect, classes.index(size));
+        }
+    }
+}
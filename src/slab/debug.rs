@@ -0,0 +1,183 @@
+use super::def::QUARANTINE_SIZE;
+use core::{alloc::Layout, fmt, mem::size_of};
+
+pub(crate) const POISON: u8 = 0x6b; // 空闲对象的填充字节
+pub(crate) const REDZONE: u8 = 0xbb; // 对象前后保护区的填充字节
+const HEADER_SIZE: usize = 2 * size_of::<usize>(); // 前保护区中保存大小和对齐的字节数
+const REDZONE_SIZE: usize = HEADER_SIZE + 16; // 对象前保护区的最小字节数
+
+/// 调试模式发现的内存破坏
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptKind {
+    UseAfterFree, // 空闲对象被写入
+    Underflow,    // 对象前的保护区被写入
+    Overflow,     // 对象后的保护区被写入
+}
+
+/// 内存破坏的报告，offset 为被破坏字节相对对象所在块的偏移
+#[derive(Debug, Clone, Copy)]
+pub struct Corruption {
+    pub kind: CorruptKind,
+    pub class: usize, // 类别大小
+    pub addr: usize,  // 交给使用者的地址
+    pub offset: usize,
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "slab corruption: {:?} of object {:#x} in size-{} at offset {}",
+            self.kind, self.addr, self.class, self.offset
+        )
+    }
+}
+
+// 带保护区的布局，以及对象相对块起始的偏移
+// 前保护区按对象对齐，使对象地址仍满足对齐要求，后保护区至少与前保护区一样大
+pub(crate) fn redzone_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let offset = core::cmp::max(REDZONE_SIZE, layout.align());
+    let size = layout.size().checked_add(offset * 2)?;
+    let layout = Layout::from_size_align(size, layout.align()).ok()?;
+    Some((layout, offset))
+}
+
+fn find(start: usize, end: usize, value: u8) -> Option<usize> {
+    (start..end).find(|&addr| unsafe { *(addr as *const u8) } != value)
+}
+
+fn report(kind: CorruptKind, class: usize, block: usize, addr: usize, bad: usize) -> ! {
+    panic!(
+        "{}",
+        Corruption {
+            kind,
+            class,
+            addr,
+            offset: bad - block,
+        }
+    )
+}
+
+// 填充整个块，保留第一个字给空闲链表
+pub(crate) unsafe fn poison(block: *mut u8, class: usize) {
+    if class > size_of::<usize>() {
+        block
+            .add(size_of::<usize>())
+            .write_bytes(POISON, class - size_of::<usize>());
+    }
+}
+
+// 检查块在空闲期间没有被写入，第一个字被空闲链表使用，不检查
+pub(crate) unsafe fn verify(block: *mut u8, class: usize, offset: usize) {
+    let start = block as usize;
+    if let Some(bad) = find(start + size_of::<usize>(), start + class, POISON) {
+        report(CorruptKind::UseAfterFree, class, start, start + offset, bad);
+    }
+}
+
+// 分配前检查毒化字节，然后填充保护区
+// 前保护区的前两个字保存请求的大小和对齐，使只根据指针释放时也能检查保护区和统计
+// 类别放不下保护区的对象偏移为0，只有后保护区
+pub(crate) unsafe fn arm(block: *mut u8, class: usize, offset: usize, layout: Layout) {
+    verify(block, class, offset);
+    if offset != 0 {
+        let words = block as *mut usize;
+        words.write(layout.size());
+        words.add(1).write(layout.align());
+        block
+            .add(HEADER_SIZE)
+            .write_bytes(REDZONE, offset - HEADER_SIZE);
+    }
+    block
+        .add(offset + layout.size())
+        .write_bytes(REDZONE, class - offset - layout.size());
+}
+
+// 分配时请求的布局，没有前保护区时由后保护区的起始位置推算大小
+// 保存的对齐与对象的偏移不一致时说明指针不是分配时返回的地址，返回None
+pub(crate) unsafe fn stored_layout(block: *mut u8, class: usize, offset: usize) -> Option<Layout> {
+    if offset == 0 {
+        let mut size = class;
+        while size > 0 && *block.add(size - 1) == REDZONE {
+            size -= 1;
+        }
+        return Layout::from_size_align(size, 1).ok();
+    }
+    let words = block as *const usize;
+    let layout = Layout::from_size_align(words.read(), words.add(1).read()).ok()?;
+    match redzone_layout(layout) {
+        Some((inner, start)) if start == offset && inner.size() <= class => Some(layout),
+        _ => None,
+    }
+}
+
+// 释放时检查保护区没有被写入
+pub(crate) unsafe fn check(block: *mut u8, class: usize, offset: usize, size: usize) {
+    let start = block as usize;
+    if let Some(bad) = find(start + HEADER_SIZE, start + offset, REDZONE) {
+        report(CorruptKind::Underflow, class, start, start + offset, bad);
+    }
+    if let Some(bad) = find(start + offset + size, start + class, REDZONE) {
+        report(CorruptKind::Overflow, class, start, start + offset, bad);
+    }
+}
+
+/// 隔离区，最近释放的对象先在这里停留，按先进先出离开后才能被重新分配
+/// 使悬垂指针不会立即指向新分配的对象，离开时检查毒化字节
+#[derive(Debug)]
+pub(crate) struct Quarantine {
+    objs: [(usize, usize, usize); QUARANTINE_SIZE], // 块地址、所属类别和对象偏移
+    head: usize,                                    // 最早进入的对象
+    len: usize,
+    depth: usize, // 最多停留的对象数，为0时不隔离
+}
+
+impl Quarantine {
+    pub const fn new() -> Self {
+        Self {
+            objs: [(0, 0, 0); QUARANTINE_SIZE],
+            head: 0,
+            len: 0,
+            depth: 0,
+        }
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = core::cmp::min(depth, QUARANTINE_SIZE);
+    }
+
+    // 取出最早进入的对象
+    pub fn pop(&mut self) -> Option<(usize, usize, usize)> {
+        if self.len == 0 {
+            return None;
+        }
+        let obj = self.objs[self.head];
+        self.head = (self.head + 1) % QUARANTINE_SIZE;
+        self.len -= 1;
+        Some(obj)
+    }
+
+    // 放入对象，超过深度时返回需要离开的对象
+    pub fn push(&mut self, obj: (usize, usize, usize)) -> Option<(usize, usize, usize)> {
+        let evicted = if self.len == self.depth {
+            self.pop()
+        } else {
+            None
+        };
+        if self.depth == 0 {
+            return Some(obj);
+        }
+        self.objs[(self.head + self.len) % QUARANTINE_SIZE] = obj;
+        self.len += 1;
+        evicted
+    }
+
+    // 超过深度的对象
+    pub fn excess(&mut self) -> Option<(usize, usize, usize)> {
+        if self.len > self.depth {
+            self.pop()
+        } else {
+            None
+        }
+    }
+}
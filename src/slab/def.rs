@@ -1 +1,43 @@
+use crate::buddy::buddy_allocator::BuddyErr;
 
+pub(crate) const MAX_CACHES: usize = 16; // 命名缓存的最大数量
+pub(crate) const MAX_CLASSES: usize = 32; // 大小类别的最大数量
+pub(crate) const MAX_CPUS: usize = 8; // 每CPU缓存支持的最大CPU数
+pub(crate) const MAG_SIZE: usize = 16; // 每个magazine最多缓存的对象数
+#[cfg(feature = "lock_free")]
+pub(crate) const STACK_SIZE: usize = 64; // 无锁模式下每个类别的栈最多积压的对象数
+pub(crate) const MAX_SHRINKERS: usize = 8; // 收缩回调的最大数量
+pub(crate) const SLAB_MAX_ORDER: usize = 4; // 为减少浪费而增大slab时的最大阶数
+pub(crate) const EMPTY_SLAB_LIMIT: usize = 1; // 每个内存池默认保留的空slab数
+#[cfg(feature = "debug")]
+pub(crate) const QUARANTINE_SIZE: usize = 64; // 调试模式下隔离区最多容纳的对象数
+
+#[derive(Debug)]
+pub enum SlabErr {
+    Buddy(BuddyErr), // 页内存分配器出错
+    WrongSize,
+    WrongAlign,
+    NotFound,     // 缓存不存在
+    CacheFull,    // 缓存数量已达上限
+    Busy,         // 缓存中仍有正在使用的对象
+    NoMemory,     // 没有可用的内存
+    Reentrant,    // 同一CPU在持有锁时再次进入分配器
+    WouldBlock,   // 锁被占用
+    ShrinkerFull, // 收缩回调数量已达上限
+    SizeMismatch, // 释放时的大小与分配时不一致
+    InvalidFree,  // 释放的指针不是分配器分配的对象
+    DoubleFree,   // 对象已经被释放过
+}
+
+/// 收缩回调，内存不足时调用，应将自己缓存的内存释放回分配器
+pub type Shrinker = fn();
+
+/// 违规处理函数，通过 GlobalAlloc 或 Allocator 释放时发现重复释放、
+/// 无效指针或大小不一致时调用，参数为错误和释放的指针
+pub type ViolationHandler = fn(SlabErr, *mut u8);
+
+impl From<BuddyErr> for SlabErr {
+    fn from(value: BuddyErr) -> Self {
+        Self::Buddy(value)
+    }
+}
@@ -0,0 +1,299 @@
+#[cfg(feature = "lock_free")]
+use super::def::STACK_SIZE;
+use super::{
+    def::{MAG_SIZE, MAX_CLASSES},
+    slab_allocator::SlabAllocator,
+};
+#[cfg(feature = "lock_free")]
+use crate::linklist::atomic::AtomicStack;
+use core::ops::DerefMut;
+#[cfg(feature = "lock_free")]
+use core::ptr::null_mut;
+#[cfg(feature = "lock_free")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// 每CPU缓存的一组对象，按后进先出使用
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Magazine {
+    objs: [usize; MAG_SIZE],
+    len: usize,
+}
+
+impl Magazine {
+    pub const fn new() -> Self {
+        Self {
+            objs: [0; MAG_SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == MAG_SIZE
+    }
+
+    pub fn pop(&mut self) -> Option<*mut u8> {
+        if self.is_empty() {
+            None
+        } else {
+            self.len -= 1;
+            Some(self.objs[self.len] as *mut u8)
+        }
+    }
+
+    pub fn push(&mut self, ptr: *mut u8) {
+        self.objs[self.len] = ptr as usize;
+        self.len += 1;
+    }
+}
+
+/// 一个CPU的对象缓存，每个大小类别一个magazine
+/// 空时从共享的内存池批量补充，满时批量归还一半
+#[derive(Debug)]
+pub(crate) struct CpuCache {
+    mags: [Magazine; MAX_CLASSES],
+}
+
+impl CpuCache {
+    pub const fn new() -> Self {
+        Self {
+            mags: [Magazine::new(); MAX_CLASSES],
+        }
+    }
+
+    // 从magazine分配，空时先从内存池补充一半
+    // slab 在需要时获取内存池的锁，获取失败时分配失败
+    pub unsafe fn allocate<const PGSZ: usize, const MAX_ORDER: usize, G>(
+        &mut self,
+        class: usize,
+        slab: impl FnOnce() -> Option<G>,
+    ) -> Option<*mut u8>
+    where
+        G: DerefMut<Target = SlabAllocator<PGSZ, MAX_ORDER>>,
+    {
+        let mag = &mut self.mags[class];
+        if mag.is_empty() {
+            let mut slab = slab()?;
+            while mag.len < MAG_SIZE / 2 {
+                match slab.allocate_class(class) {
+                    Some(ptr) => mag.push(ptr),
+                    None => break,
+                }
+            }
+        }
+        mag.pop()
+    }
+
+    // 释放到magazine，满时先将一半归还给内存池，获取内存池的锁失败时返回false
+    pub unsafe fn deallocate<const PGSZ: usize, const MAX_ORDER: usize, G>(
+        &mut self,
+        class: usize,
+        ptr: *mut u8,
+        slab: impl FnOnce() -> Option<G>,
+    ) -> bool
+    where
+        G: DerefMut<Target = SlabAllocator<PGSZ, MAX_ORDER>>,
+    {
+        let mag = &mut self.mags[class];
+        if mag.is_full() {
+            let mut slab = match slab() {
+                Some(slab) => slab,
+                None => return false,
+            };
+            while mag.len > MAG_SIZE / 2 {
+                let obj = mag.pop().expect("magazine is empty");
+                slab.deallocate_class(class, obj);
+            }
+        }
+        mag.push(ptr);
+        true
+    }
+
+    // 将所有缓存的对象归还给内存池
+    pub unsafe fn drain<const PGSZ: usize, const MAX_ORDER: usize>(
+        &mut self,
+        slab: &mut SlabAllocator<PGSZ, MAX_ORDER>,
+    ) {
+        for (class, mag) in self.mags.iter_mut().enumerate() {
+            while let Some(obj) = mag.pop() {
+                slab.deallocate_class(class, obj);
+            }
+        }
+    }
+}
+
+/// 无锁模式下每个大小类别的空闲对象栈，在所有CPU之间共享，是小对象主要的空闲链表
+/// 分配先从栈中弹出，栈空时才加锁从内存池补充一批；释放总是压栈，不会阻塞，
+/// 栈中积压超过 STACK_SIZE 个对象时不等待地获取锁，将一半归还给内存池
+#[cfg(feature = "lock_free")]
+#[derive(Debug)]
+pub(crate) struct FreeStacks {
+    base: AtomicUsize, // 被管理内存的起始地址
+    stacks: [AtomicStack; MAX_CLASSES],
+    lens: [AtomicUsize; MAX_CLASSES], // 栈中对象数的上界，压栈前加一、弹出后减一
+}
+
+#[cfg(feature = "lock_free")]
+impl FreeStacks {
+    // 仅用于初始化栈数组
+    #[allow(clippy::declare_interior_mutable_const)]
+    const STACK: AtomicStack = AtomicStack::new();
+    #[allow(clippy::declare_interior_mutable_const)]
+    const LEN: AtomicUsize = AtomicUsize::new(0);
+
+    pub const fn new() -> Self {
+        Self {
+            base: AtomicUsize::new(0),
+            stacks: [Self::STACK; MAX_CLASSES],
+            lens: [Self::LEN; MAX_CLASSES],
+        }
+    }
+
+    pub fn set_base(&self, base: usize) {
+        self.base.store(base, Ordering::Release);
+    }
+
+    // 设置编码栈中next的密钥，栈需要为空
+    #[cfg(feature = "hardened")]
+    pub fn set_key(&self, key: usize) {
+        for stack in self.stacks.iter() {
+            stack.set_key(key);
+        }
+    }
+
+    unsafe fn push(&self, class: usize, ptr: *mut u8) {
+        self.lens[class].fetch_add(1, Ordering::Relaxed);
+        self.stacks[class].push(self.base.load(Ordering::Acquire), ptr as usize);
+    }
+
+    // valid检查栈中解出的地址是该类别中对象的起始地址
+    unsafe fn pop(&self, class: usize, valid: &impl Fn(usize, usize) -> bool) -> Option<*mut u8> {
+        let obj = self.stacks[class]
+            .pop_checked(self.base.load(Ordering::Acquire), |addr| valid(class, addr))?;
+        self.lens[class].fetch_sub(1, Ordering::Relaxed);
+        Some(obj as *mut u8)
+    }
+
+    // 栈空时slab获取内存池的锁，从同一个slab补充至多半个magazine的对象，
+    // 不会为补充而占用更多的slab，获取锁失败或内存不足时分配失败
+    pub unsafe fn allocate<const PGSZ: usize, const MAX_ORDER: usize, G>(
+        &self,
+        class: usize,
+        valid: impl Fn(usize, usize) -> bool,
+        slab: impl FnOnce() -> Option<G>,
+    ) -> Option<*mut u8>
+    where
+        G: DerefMut<Target = SlabAllocator<PGSZ, MAX_ORDER>>,
+    {
+        if let Some(obj) = self.pop(class, &valid) {
+            return Some(obj);
+        }
+
+        let mut slab = slab()?;
+        let obj = slab.allocate_class(class)?;
+        // 逆序压栈，之后仍按slab中的顺序分配
+        let mut objs = [null_mut(); MAG_SIZE / 2 - 1];
+        let mut len = 0;
+        while len < objs.len() {
+            match slab.allocate_partial(class) {
+                Some(ptr) => objs[len] = ptr,
+                None => break,
+            }
+            len += 1;
+        }
+        for &ptr in objs[..len].iter().rev() {
+            self.push(class, ptr);
+        }
+        Some(obj)
+    }
+
+    // 释放总是压栈，积压过多时slab在不等待时获取内存池的锁，获取失败时继续积压
+    pub unsafe fn deallocate<const PGSZ: usize, const MAX_ORDER: usize, G>(
+        &self,
+        class: usize,
+        ptr: *mut u8,
+        valid: impl Fn(usize, usize) -> bool,
+        slab: impl FnOnce() -> Option<G>,
+    ) where
+        G: DerefMut<Target = SlabAllocator<PGSZ, MAX_ORDER>>,
+    {
+        self.push(class, ptr);
+        if self.lens[class].load(Ordering::Relaxed) <= STACK_SIZE {
+            return;
+        }
+        if let Some(mut slab) = slab() {
+            while self.lens[class].load(Ordering::Relaxed) > STACK_SIZE / 2 {
+                match self.pop(class, &valid) {
+                    Some(obj) => slab.deallocate_class(class, obj),
+                    None => break,
+                }
+            }
+        }
+    }
+
+    // 将栈中的对象全部归还给内存池
+    pub unsafe fn drain<const PGSZ: usize, const MAX_ORDER: usize>(
+        &self,
+        slab: &mut SlabAllocator<PGSZ, MAX_ORDER>,
+        valid: impl Fn(usize, usize) -> bool,
+    ) {
+        for class in 0..MAX_CLASSES {
+            while let Some(obj) = self.pop(class, &valid) {
+                slab.deallocate_class(class, obj);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "lock_free"))]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::def::PGSZ;
+    use std::vec::Vec;
+
+    #[test]
+    fn stack_test() {
+        let mem = [0usize; 4096 * 4];
+        let bottom = mem.as_ptr() as usize;
+        let mut slab: SlabAllocator<PGSZ, 32> = SlabAllocator::new();
+        let stacks = FreeStacks::new();
+        unsafe {
+            slab.init(bottom, bottom + mem.len() * 8);
+            stacks.set_base(bottom);
+            let none = || None::<&mut SlabAllocator<PGSZ, 32>>;
+            let any = |_, _| true;
+
+            // 栈空时加锁补充，之后的分配和释放都不需要锁
+            let first = stacks.allocate(0, any, || Some(&mut slab)).unwrap();
+            let mut objs = Vec::from([first]);
+            for _ in 1..MAG_SIZE / 2 {
+                objs.push(stacks.allocate(0, any, none).unwrap());
+            }
+            #[cfg(not(feature = "randomize"))]
+            assert!(objs.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(stacks.allocate(0, any, none).is_none());
+            for &obj in objs.iter() {
+                stacks.deallocate(0, obj, any, none);
+            }
+            let last = *objs.last().unwrap();
+            assert_eq!(last, stacks.allocate(0, any, none).unwrap());
+            stacks.deallocate(0, last, any, none);
+
+            // 积压超过上限时归还一半
+            for _ in 0..STACK_SIZE {
+                let obj = stacks.allocate(0, any, || Some(&mut slab)).unwrap();
+                objs.push(obj);
+            }
+            for &obj in objs.iter().skip(MAG_SIZE / 2) {
+                stacks.deallocate(0, obj, any, || Some(&mut slab));
+            }
+            assert!(stacks.lens[0].load(Ordering::Relaxed) <= STACK_SIZE);
+            stacks.drain(&mut slab, any);
+            assert_eq!(0, stacks.lens[0].load(Ordering::Relaxed));
+        }
+    }
+}
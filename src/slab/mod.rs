@@ -1,3 +1,10 @@
+pub mod cache;
+pub mod class;
+#[cfg(feature = "debug")]
+pub mod debug;
 pub(crate) mod def;
+pub(crate) mod magazine;
+pub(crate) mod page;
 pub mod slab_allocator;
 pub mod slab_lock;
+pub mod stats;
@@ -0,0 +1,255 @@
+use crate::{align_down, linklist::link::Linkedlist};
+use core::{
+    mem::size_of,
+    ptr::{null, null_mut},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// 页的用途，释放时据此判断指针属于哪个内存池或页块
+/// 数值使用u32，使描述符的大小不变
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PageKind {
+    Other,      // 空闲页，或不由 allocate_fit 管理的页
+    Slab(u32),  // slab的第一页，记录所属内存池的索引
+    Tail(u32),  // 多页slab的后续页，记录相对第一页的页数
+    Pages(u32), // allocate_fit 直接从页内存分配器分配的页块的第一页，记录页数
+    Cache(u32), // 命名缓存的slab的第一页，记录缓存的序号
+}
+
+/// 指针所属的slab或页块
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Owner {
+    Slab { class: usize, start: usize },
+    Pages { start: usize, pages: usize },
+    Cache { index: usize, start: usize },
+}
+
+/// slab页描述符
+/// 每个slab拥有自己的空闲链表，并通过prev/next挂在所属内存池的某个链表上
+/// 多页slab只使用第一页的描述符，后续页的描述符记录到第一页的距离
+#[derive(Debug)]
+#[repr(C)]
+pub(crate) struct SlabPage {
+    pub(crate) kind: PageKind,   // 页的用途
+    pub(crate) inuse: usize,     // 页中正在使用的对象数
+    pub(crate) free: Linkedlist, // 页中的空闲对象
+    prev: *mut SlabPage,
+    next: *mut SlabPage,
+}
+
+impl SlabPage {
+    pub const fn new() -> Self {
+        Self {
+            kind: PageKind::Other,
+            inuse: 0,
+            free: Linkedlist::new(),
+            prev: null_mut(),
+            next: null_mut(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inuse == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.free.is_empty()
+    }
+}
+
+/// slab页描述符组成的双向链表
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PageList {
+    head: *mut SlabPage,
+    len: usize,
+}
+
+impl PageList {
+    pub const fn new() -> Self {
+        Self {
+            head: null_mut(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn head(&self) -> Option<*mut SlabPage> {
+        if self.head.is_null() {
+            None
+        } else {
+            Some(self.head)
+        }
+    }
+
+    pub unsafe fn push(&mut self, page: *mut SlabPage) {
+        (*page).prev = null_mut();
+        (*page).next = self.head;
+        if !self.head.is_null() {
+            (*self.head).prev = page;
+        }
+        self.head = page;
+        self.len += 1;
+    }
+
+    // 从链表中移除page，page需要在该链表中
+    pub unsafe fn remove(&mut self, page: *mut SlabPage) {
+        let prev = (*page).prev;
+        let next = (*page).next;
+
+        if prev.is_null() {
+            self.head = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+
+        (*page).prev = null_mut();
+        (*page).next = null_mut();
+        self.len -= 1;
+    }
+}
+
+/// 同一大小对象的所有slab，按使用情况分为三个链表
+/// 分配时优先使用部分使用的slab，其次是空slab
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SlabPool {
+    pub(crate) partial: PageList, // 部分使用
+    pub(crate) full: PageList,    // 全部使用
+    pub(crate) empty: PageList,   // 全部空闲
+}
+
+impl SlabPool {
+    pub const fn new() -> Self {
+        Self {
+            partial: PageList::new(),
+            full: PageList::new(),
+            empty: PageList::new(),
+        }
+    }
+}
+
+/// slab页描述符表，被管理内存中的每一页对应一个描述符
+/// 另有对象的使用位图，每页对应bits位，记录slab中的对象是否已分配给用户
+/// 描述符表和位图分别保存在从页内存分配器分配的页中
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PageTable<const PGSZ: usize> {
+    table: *mut SlabPage,
+    used: *const AtomicU64, // 对象的使用位图
+    base: usize,            // 被管理内存的起始地址
+    counts: usize,          // 描述符个数
+    bits: usize,            // 每页在位图中的位数
+}
+
+// 描述符只在持有内存池锁时修改，不加锁读取的是正在使用的对象所在页的描述符，此时不会被修改
+unsafe impl<const PGSZ: usize> Send for PageTable<PGSZ> {}
+unsafe impl<const PGSZ: usize> Sync for PageTable<PGSZ> {}
+
+impl<const PGSZ: usize> PageTable<PGSZ> {
+    pub const fn new() -> Self {
+        Self {
+            table: null_mut(),
+            used: null(),
+            base: 0,
+            counts: 0,
+            bits: 0,
+        }
+    }
+
+    // counts个描述符需要的内存大小
+    pub const fn table_size(counts: usize) -> usize {
+        counts * size_of::<SlabPage>()
+    }
+
+    // counts页、每页bits位的位图需要的内存大小
+    pub const fn map_size(counts: usize, bits: usize) -> usize {
+        (counts * bits).div_ceil(64) * size_of::<u64>()
+    }
+
+    /// # Safety
+    /// table处需要有 table_size(counts) 字节、used处需要有 map_size(counts, bits) 字节可用的内存
+    pub unsafe fn init(
+        &mut self,
+        table: usize,
+        used: usize,
+        base: usize,
+        counts: usize,
+        bits: usize,
+    ) {
+        self.table = table as *mut SlabPage;
+        self.used = used as *const AtomicU64;
+        self.base = base;
+        self.counts = counts;
+        self.bits = bits;
+
+        for i in 0..counts {
+            self.table.add(i).write(SlabPage::new());
+        }
+        for i in 0..(counts * bits).div_ceil(64) {
+            (self.used.add(i) as *mut AtomicU64).write(AtomicU64::new(0));
+        }
+    }
+
+    // 设置起始地址为start的slab中第obj个对象的使用标记，返回原来的标记
+    pub fn mark(&self, start: usize, obj: usize, used: bool) -> bool {
+        let bit = (start - self.base) / PGSZ * self.bits + obj;
+        let word = unsafe { &*self.used.add(bit / 64) };
+        let mask = 1 << (bit % 64);
+        let old = if used {
+            word.fetch_or(mask, Ordering::AcqRel)
+        } else {
+            word.fetch_and(!mask, Ordering::AcqRel)
+        };
+        old & mask != 0
+    }
+
+    // 获取地址所在页的描述符
+    pub fn get(&self, addr: usize) -> Option<*mut SlabPage> {
+        if addr < self.base || (addr - self.base) / PGSZ >= self.counts {
+            return None;
+        }
+
+        unsafe { Some(self.table.add((addr - self.base) / PGSZ)) }
+    }
+
+    // 地址所属的slab或页块，页块只能由其第一页找到
+    pub fn owner(&self, addr: usize) -> Option<Owner> {
+        let mut page = self.get(addr)?;
+        unsafe {
+            if let PageKind::Tail(offset) = (*page).kind {
+                page = page.sub(offset as usize);
+            }
+            let start = self.page_addr(page);
+            match (*page).kind {
+                PageKind::Slab(class) => Some(Owner::Slab {
+                    class: class as usize,
+                    start,
+                }),
+                PageKind::Pages(pages) => Some(Owner::Pages {
+                    start,
+                    pages: pages as usize,
+                }),
+                PageKind::Cache(index) => Some(Owner::Cache {
+                    index: index as usize,
+                    start,
+                }),
+                _ => None,
+            }
+        }
+    }
+
+    // 描述符对应页的起始地址
+    pub fn page_addr(&self, page: *const SlabPage) -> usize {
+        let index = (page as usize - self.table as usize) / size_of::<SlabPage>();
+        self.base + index * PGSZ
+    }
+
+    // 地址所在slab的起始地址，slab由页内存分配器分配，因此相对base按slab_size对齐
+    pub fn slab_start(&self, addr: usize, slab_size: usize) -> usize {
+        self.base + align_down!(addr - self.base, slab_size)
+    }
+}
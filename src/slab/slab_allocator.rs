@@ -1,159 +1,577 @@
-use crate::def::PGSZ;
-use crate::{
-    align_up,
-    linklist::{def::*, link::Linkedlist},
-    BuddyAllocator,
+use super::{
+    cache::{CacheId, Ctor, ObjCache},
+    class::{SizeClasses, POW2_CLASSES},
+    def::{SlabErr, EMPTY_SLAB_LIMIT, MAX_CACHES, MAX_CLASSES, SLAB_MAX_ORDER},
+    page::{Owner, PageKind, PageTable, SlabPage, SlabPool},
 };
-use core::{
-    alloc::Layout,
-    ops::{Index, IndexMut},
-    ptr::null_mut,
+#[cfg(feature = "hardened")]
+use crate::rand::mix;
+#[cfg(feature = "randomize")]
+use crate::rand::Rng;
+use crate::{
+    align_up, buddy::buddy_allocator::BuddyErr, is_align, trace::Event, trace_event, BuddyAllocator,
 };
-use xxos_log::{error, info};
+use core::{alloc::Layout, mem::size_of, ops::IndexMut, ptr::null_mut};
 
 /// 小内存分配器
-/// 基于页内存分配器，使用了8 个内存池，分配对应大小的内存
-/// 最大不超过一个页，超过一个页则调用页内存分配器直接获取
-/// 对应大小内存
-pub struct SlabAllocator {
-    pub(crate) pool: [Linkedlist; POOL_COUNT],
-    pub(crate) buddy: BuddyAllocator,
+/// 基于页内存分配器，每个大小类别对应一个内存池，分配对应大小的内存
+/// 大小类别在构造时选择，默认为32到32768的2的幂
+/// 超过最大类别则调用页内存分配器直接获取对应大小内存
+pub struct SlabAllocator<
+    const PGSZ: usize = { crate::def::PGSZ },
+    const MAX_ORDER: usize = { crate::def::MAX_ORDER },
+> {
+    pub(crate) pool: [SlabPool; MAX_CLASSES],
+    pub(crate) classes: SizeClasses,
+    pub(crate) buddy: BuddyAllocator<PGSZ, MAX_ORDER>,
+    pub(crate) caches: [ObjCache; MAX_CACHES],
+    pub(crate) pages: PageTable<PGSZ>, // slab页描述符
+    empty_limit: usize,
+    #[cfg(feature = "hardened")]
+    secret: usize, // 编码slab空闲链表的密钥
+    #[cfg(feature = "randomize")]
+    rng: Rng, // 打乱新slab中对象的顺序
 }
 
-unsafe impl Send for SlabAllocator {}
+unsafe impl<const PGSZ: usize, const MAX_ORDER: usize> Send for SlabAllocator<PGSZ, MAX_ORDER> {}
 
-impl SlabAllocator {
+impl<const PGSZ: usize, const MAX_ORDER: usize> SlabAllocator<PGSZ, MAX_ORDER> {
     pub const fn new() -> Self {
+        Self::with_classes(&POW2_CLASSES)
+    }
+
+    // 使用指定的大小类别
+    pub const fn with_classes(classes: &[usize]) -> Self {
         Self {
-            pool: [Linkedlist::new(); POOL_COUNT],
+            pool: [SlabPool::new(); MAX_CLASSES],
+            classes: SizeClasses::new(classes),
             buddy: BuddyAllocator::new(),
+            caches: [ObjCache::new(); MAX_CACHES],
+            pages: PageTable::new(),
+            empty_limit: EMPTY_SLAB_LIMIT,
+            #[cfg(feature = "hardened")]
+            secret: 0,
+            #[cfg(feature = "randomize")]
+            rng: Rng::new(),
         }
     }
 
-    pub fn align_layout(layout: Layout) -> Result<Layout, ()> {
-        fn find_fit_size(size: usize) -> usize {
-            match size {
-                0 => 0,
-                sz if sz > 0 && sz <= POOL_SIZE_32 => POOL_SIZE_32,
-                sz if sz > POOL_SIZE_32 && sz <= POOL_SIZE_64 => POOL_SIZE_64,
-                sz if sz > POOL_SIZE_64 && sz <= POOL_SIZE_128 => POOL_SIZE_128,
-                sz if sz > POOL_SIZE_128 && sz <= POOL_SIZE_256 => POOL_SIZE_256,
-                sz if sz > POOL_SIZE_256 && sz <= POOL_SIZE_512 => POOL_SIZE_512,
-                sz if sz > POOL_SIZE_512 && sz <= POOL_SIZE_1024 => POOL_SIZE_1024,
-                sz if sz > POOL_SIZE_1024 && sz <= POOL_SIZE_2048 => POOL_SIZE_2048,
-                sz if sz > POOL_SIZE_2048 && sz <= POOL_SIZE_4096 => POOL_SIZE_4096,
-                _ => align_up!(size, PGSZ),
-            }
-        }
-
-        fn get_algin(v1: usize, v2: usize) -> usize {
-            core::cmp::max(v1, v2)
-        }
-
-        info!(
-            "the layout before find  is size {} algin {}",
-            layout.size(),
-            layout.align()
-        );
+    // 能满足layout的大小类别，没有时由页内存分配器分配
+    // 对象按类别大小中最大的2的幂因子自然对齐，对齐要求更大时使用能满足对齐的更大类别，
+    // 超过页大小的对齐由页内存分配器满足
+    fn fit_class(&self, layout: Layout) -> Option<usize> {
+        self.classes.index_layout(layout, PGSZ)
+    }
 
-        let fit_size = find_fit_size(layout.size());
-        let algin = get_algin(layout.align(), PGSZ);
+    // 小内存按类别的自然对齐，大内存至少按页对齐
+    // 页内存分配器分配的块为2的幂个页，大内存按实际占用的块计算大小
+    pub fn align_layout(&self, layout: Layout) -> Result<Layout, ()> {
+        let (fit_size, algin) = match self.fit_class(layout) {
+            Some(index) => (
+                self.classes.size(index),
+                self.classes.slab_align(index, PGSZ),
+            ),
+            None if layout.size() == 0 => (0, layout.align()),
+            None => (
+                align_up!(layout.size(), PGSZ).next_power_of_two(),
+                core::cmp::max(layout.align(), PGSZ),
+            ),
+        };
 
-        info!("the layout is size {:#x} algin {:#x}", fit_size, algin);
         Layout::from_size_align(fit_size, algin).map_err(|_| ())
     }
 
     pub unsafe fn init(&mut self, bottom: usize, top: usize) {
         self.buddy.init(bottom, top);
+
+        // 描述符表和对象的使用位图从页内存分配器中分配
+        let counts = self.buddy.total_pages();
+        let table_size = align_up!(PageTable::<PGSZ>::table_size(counts), PGSZ);
+        let layout = Layout::from_size_align(table_size, PGSZ).expect("err");
+        let table = self
+            .buddy
+            .allocate(layout)
+            .expect("no memory for slab page table");
+
+        // 每页的位数按最小的对象计算，命名缓存的对象最小为一个字，可能小于最小的类别
+        // 多页slab中的对象也不会超过其所有页的位数
+        let bits = PGSZ / size_of::<usize>();
+        let map_size = align_up!(PageTable::<PGSZ>::map_size(counts, bits), PGSZ);
+        let layout = Layout::from_size_align(map_size, PGSZ).expect("err");
+        let used = self
+            .buddy
+            .allocate(layout)
+            .expect("no memory for slab object map");
+        self.pages
+            .init(table, used, self.buddy.zone_start(), counts, bits);
+
+        #[cfg(feature = "randomize")]
+        self.rng.seed((bottom ^ top.rotate_left(29)) as u64);
+
+        // 由地址生成确定的默认密钥，有随机数来源时应在分配前通过 set_secret 替换
+        #[cfg(feature = "hardened")]
+        {
+            let seed = bottom ^ top.rotate_left(17) ^ (self as *const _ as usize);
+            self.secret = mix(seed as u64) as usize;
+        }
     }
 
-    unsafe fn allocate<T>(&mut self, index: usize, layout: Layout) -> Option<*mut T> {
-        info!("allocate the index is {}", index);
+    // 设置打乱分配顺序的随机数种子，页内存分配器使用由其派生的种子
+    #[cfg(feature = "randomize")]
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng.seed(seed);
+        self.buddy.set_seed(self.rng.next_u64());
+    }
 
-        if let Some(ptr) = self.pool.index_mut(index).pop_algin::<T>(layout.size()) {
-            info!("it alloced!");
-            Some(ptr)
-        } else {
-            info!("none value in pool , go to buddy to alloc new page!");
-            info!("the size is {:#x}!", layout.size());
-            let alloc_from_body = Layout::from_size_align(PGSZ, PGSZ).expect("err");
-            //TODO it should have error handle
-            let page = self.buddy.allocate(alloc_from_body).expect("None Page");
+    // 设置编码空闲链表的密钥，只对之后建立的slab生效
+    #[cfg(feature = "hardened")]
+    pub fn set_secret(&mut self, secret: usize) {
+        self.secret = secret;
+    }
+
+    #[cfg(all(feature = "hardened", feature = "lock_free"))]
+    pub(crate) fn secret(&self) -> usize {
+        self.secret
+    }
 
-            let start = page;
+    // 地址为addr的空闲链表的密钥
+    #[cfg(feature = "hardened")]
+    fn key(&self, addr: usize) -> usize {
+        self.secret ^ mix(addr as u64) as usize
+    }
+
+    // 设置每个内存池最多保留的空slab数
+    // 内存池按部分使用、全部使用、全部空闲三个链表管理slab，多余的空slab归还给页内存分配器
+    pub fn set_empty_limit(&mut self, limit: usize) {
+        self.empty_limit = limit;
+    }
+
+    // slab由足够容纳一个对象的2的幂个连续页组成
+    // 末尾浪费超过slab的1/8时尝试更大的slab，最大为 SLAB_MAX_ORDER 阶，都不满足时取浪费比例最小的
+    fn slab_size(size: usize) -> usize {
+        let mut best = core::cmp::max(PGSZ, size).next_power_of_two();
+        let mut slab = best;
+        while slab <= PGSZ << SLAB_MAX_ORDER {
+            if slab % size * 8 <= slab {
+                return slab;
+            }
+            if slab % size * best < best % size * slab {
+                best = slab;
+            }
+            slab *= 2;
+        }
+        best
+    }
 
-            info!("it got a page {:#x}!", page);
+    // 第index个内存池持有的页数
+    pub(crate) fn pool_pages(&self, index: usize) -> usize {
+        let pool = &self.pool[index];
+        let slabs = pool.partial.len() + pool.full.len() + pool.empty.len();
+        slabs * Self::slab_size(self.classes.size(index)) / PGSZ
+    }
 
-            let end = start + PGSZ;
-            info!("the end of the page is  {:#x}!", end);
-            assert_eq!(self.pool.index(index).len(), 0);
+    // 归还所有空slab，以及没有正在使用对象的命名缓存的slab，返回归还的页数
+    pub unsafe fn shrink(&mut self) -> usize {
+        let mut pages = 0;
+        for index in 0..self.classes.len() {
+            let size = self.classes.size(index);
+            while let Some(page) = self.pool[index].empty.head() {
+                self.release(index, page, size);
+                pages += Self::slab_size(size) / PGSZ;
+            }
+        }
+        for cache in self.caches.iter_mut() {
+            pages += cache.shrink(&mut self.buddy, &self.pages);
+        }
+        trace_event!(Event::Shrink { pages });
+        pages
+    }
 
-            self.pool.index_mut(index).init(start, end, layout.size());
+    // 从页内存分配器分配，内存不足时先回收空slab再重试
+    unsafe fn allocate_pages(&mut self, layout: Layout) -> Result<usize, BuddyErr> {
+        match self.buddy.allocate(layout) {
+            Err(_) if self.shrink() > 0 => self.buddy.allocate(layout),
+            result => result,
+        }
+    }
 
-            if layout.align() > PGSZ {
-                error!("the algin is too big , plese give a samller algin");
+    // 从页内存分配器取一个新的slab，放入内存池的空slab链表
+    unsafe fn grow(&mut self, index: usize, size: usize) -> Option<*mut SlabPage> {
+        let slab_size = Self::slab_size(size);
+        let alloc_from_body = Layout::from_size_align(slab_size, PGSZ).expect("err");
+        let start = match self.allocate_pages(alloc_from_body) {
+            Ok(page) => page,
+            Err(_) => {
+                trace_event!(Event::SlabExhausted { class: index });
                 return None;
             }
+        };
+        let end = start + slab_size;
+
+        // 调试模式下新slab中的对象同样视为已被释放
+        #[cfg(feature = "debug")]
+        (start as *mut u8).write_bytes(super::debug::POISON, slab_size);
+
+        let page = self.pages.get(start).expect("slab is out of the zone");
+        (*page).kind = PageKind::Slab(index as u32);
+        (*page).inuse = 0;
+        for i in 1..slab_size / PGSZ {
+            (*page.add(i)).kind = PageKind::Tail(i as u32);
+        }
+        #[cfg(feature = "hardened")]
+        (*page).free.set_key(self.key(start));
+        // slab只按页对齐，对象从start处开始存放，按 slab_align 对齐
+        let align = self.classes.slab_align(index, PGSZ);
+        #[cfg(not(feature = "randomize"))]
+        let objs = (*page).free.init(start, end, size, align);
+        #[cfg(feature = "randomize")]
+        let objs = (*page)
+            .free
+            .init_shuffled(start, end, size, align, &mut self.rng);
+        trace_event!(Event::SlabGrow {
+            class: index,
+            start,
+            end,
+            objs
+        });
+        self.pool.index_mut(index).empty.push(page);
+
+        Some(page)
+    }
+
+    // 将空slab归还给页内存分配器
+    unsafe fn release(&mut self, index: usize, page: *mut SlabPage, size: usize) {
+        let slab_size = Self::slab_size(size);
+        let start = self.pages.page_addr(page);
+        trace_event!(Event::SlabRelease {
+            class: index,
+            start
+        });
+
+        self.pool.index_mut(index).empty.remove(page);
+        for i in 0..slab_size / PGSZ {
+            *page.add(i) = SlabPage::new();
+        }
+        self.buddy
+            .deallocate(start, slab_size)
+            .expect("error the buddy free error");
+    }
+
+    unsafe fn allocate<T>(&mut self, index: usize, layout: Layout) -> Option<*mut T> {
+        if layout.align() > PGSZ {
+            return None;
+        }
+
+        // 优先使用部分使用的slab，其次是空slab，都没有时取一个新的slab
+        let page = match self.pool[index].partial.head() {
+            Some(page) => page,
+            None => {
+                let page = match self.pool[index].empty.head() {
+                    Some(page) => page,
+                    None => self.grow(index, layout.size())?,
+                };
+                let pool = self.pool.index_mut(index);
+                pool.empty.remove(page);
+                pool.partial.push(page);
+                page
+            }
+        };
 
-            let ptr = self
-                .pool
-                .index_mut(index)
-                .pop::<T>()
-                .expect("it no mem in this pool");
-            Some(ptr)
+        let start = self.pages.page_addr(page);
+        let end = start + Self::slab_size(layout.size());
+        let ptr = (*page)
+            .free
+            .pop_within::<T>(start, end)
+            .expect("it no mem in this pool");
+        (*page).inuse += 1;
+        trace_event!(Event::ObjAlloc {
+            class: index,
+            addr: ptr as usize
+        });
+
+        if (*page).is_full() {
+            let pool = self.pool.index_mut(index);
+            pool.partial.remove(page);
+            pool.full.push(page);
         }
+
+        Some(ptr)
+    }
+
+    // 从第index个类别分配一个对象
+    pub(crate) unsafe fn allocate_class(&mut self, index: usize) -> Option<*mut u8> {
+        let layout = Layout::from_size_align_unchecked(
+            self.classes.size(index),
+            self.classes.slab_align(index, PGSZ),
+        );
+        self.allocate(index, layout)
+    }
+
+    // 只从第index个类别部分使用的slab中分配，不取空的或新的slab
+    #[cfg(feature = "lock_free")]
+    pub(crate) unsafe fn allocate_partial(&mut self, index: usize) -> Option<*mut u8> {
+        self.pool[index].partial.head()?;
+        self.allocate_class(index)
     }
 
     pub unsafe fn allocate_fit(&mut self, layout: Layout) -> Result<*mut u8, ()> {
-        info!("allocate start ");
-        let layout = Self::align_layout(layout)?;
-        info!("get regular size ,{}", layout.size());
+        self.allocate_fit_sized(layout).map(|(ptr, _)| ptr)
+    }
+
+    // 与 allocate_fit 相同，同时返回实际可用的大小，即类别大小或页块大小
+    pub unsafe fn allocate_fit_sized(&mut self, layout: Layout) -> Result<(*mut u8, usize), ()> {
+        let layout = self.align_layout(layout)?;
         if layout.size() == 0 {
-            return Ok(null_mut());
-        }
-        let mut ptr = None;
-        let size_arr = [32, 64, 128, 256, 512, 1024, 2048, 4096];
-        let mut index = 0;
-        for pool_size in size_arr {
-            if layout.size() == pool_size {
-                info!("allocer in index {} the size is {}", index, layout.size());
-                ptr = self.allocate(index, layout);
-                break;
-            } else {
-                index += 1;
-            }
+            return Ok((null_mut(), 0));
+        }
+        if let Some(index) = self.fit_class(layout) {
+            let ptr = self.allocate(index, layout).ok_or(())?;
+            self.mark(index, ptr, true);
+            return Ok((ptr, layout.size()));
         }
 
-        if ptr.is_none() {
-            info!("the request size is more the pgsz , {}", layout.size());
-            ptr = self.buddy.allocate(layout).ok().map(|x| x as *mut _);
-        };
         //Todo it should have error handing
-        ptr.ok_or(())
+        let addr = self.allocate_pages(layout).map_err(|_| ())?;
+        self.set_pages(addr, layout.size() / PGSZ);
+        Ok((addr as *mut _, layout.size()))
+    }
+
+    // 记录直接从页内存分配器分配的页块，pages为0时清除记录
+    fn set_pages(&mut self, addr: usize, pages: usize) {
+        if let Some(page) = self.pages.get(addr) {
+            let kind = match pages {
+                0 => PageKind::Other,
+                _ => PageKind::Pages(pages as u32),
+            };
+            unsafe { (*page).kind = kind };
+        }
     }
 
-    unsafe fn deallocate(&mut self, index: usize, ptr: *mut u8) {
-        self.pool.index_mut(index).push(ptr as usize)
+    unsafe fn deallocate_pages(&mut self, addr: usize, pages: usize) -> Result<(), SlabErr> {
+        self.buddy.deallocate(addr, pages * PGSZ)?;
+        self.set_pages(addr, 0);
+        Ok(())
     }
 
-    pub unsafe fn deallocate_fit(&mut self, ptr: *mut u8, layout: Layout) {
-        let layout = Self::align_layout(layout).expect("error");
-        let size_arr = [32, 64, 128, 256, 512, 1024, 2048, 4096];
-        let mut index = 0;
-        for size in size_arr {
-            if layout.size() == size {
-                self.deallocate(index, ptr);
-            } else {
-                index += 1;
+    // 设置对象的使用标记，返回原来的标记
+    fn mark(&self, index: usize, ptr: *mut u8, used: bool) -> bool {
+        let size = self.classes.size(index);
+        let start = self.pages.slab_start(ptr as usize, Self::slab_size(size));
+        self.pages.mark(start, (ptr as usize - start) / size, used)
+    }
+
+    // 释放分配给用户的对象，使用位图中没有标记的对象说明重复释放
+    unsafe fn deallocate(
+        &mut self,
+        index: usize,
+        ptr: *mut u8,
+        size: usize,
+    ) -> Result<(), SlabErr> {
+        if !self.mark(index, ptr, false) {
+            return Err(SlabErr::DoubleFree);
+        }
+        self.put_back(index, ptr, size)
+    }
+
+    // 将对象放回所在slab的空闲链表
+    unsafe fn put_back(&mut self, index: usize, ptr: *mut u8, size: usize) -> Result<(), SlabErr> {
+        let start = self.pages.slab_start(ptr as usize, Self::slab_size(size));
+        let page = self
+            .pages
+            .get(start)
+            .expect("the object is out of the zone");
+        debug_assert_eq!(PageKind::Slab(index as u32), (*page).kind);
+
+        // 对象是空闲链表的头，或slab中没有正在使用的对象，说明重复释放
+        // 缓存中的对象没有使用标记，放回时只能由此发现紧接着的重复释放
+        if (*page).free.is_head(ptr as usize) || (*page).is_empty() {
+            return Err(SlabErr::DoubleFree);
+        }
+
+        let pool = self.pool.index_mut(index);
+        if (*page).is_full() {
+            pool.full.remove(page);
+            pool.partial.push(page);
+        }
+
+        (*page).free.push(ptr as usize);
+        (*page).inuse -= 1;
+        trace_event!(Event::ObjFree {
+            class: index,
+            addr: ptr as usize
+        });
+
+        if (*page).is_empty() {
+            pool.partial.remove(page);
+            pool.empty.push(page);
+            if pool.empty.len() > self.empty_limit {
+                self.release(index, page, size);
             }
         }
+        Ok(())
+    }
+
+    // 将缓存中的对象释放回第index个类别，对象在放入缓存前已经检查过并清除了使用标记
+    pub(crate) unsafe fn deallocate_class(&mut self, index: usize, ptr: *mut u8) {
+        let _ = self.put_back(index, ptr, self.classes.size(index));
+    }
 
-        if layout.size() > PGSZ {
-            //Todo it should have error handing
-            self.buddy
-                .deallocate(ptr as usize, layout.size())
-                .expect("error the buddy free error");
+    // 根据描述符检查layout与分配时一致，不一致时不释放
+    #[allow(unused)]
+    pub unsafe fn deallocate_fit(&mut self, ptr: *mut u8, layout: Layout) -> Result<(), SlabErr> {
+        self.deallocate_layout(ptr, layout, true)
+    }
+
+    // 与 deallocate_fit 相同，但对象的使用标记已经由 LockedSlab 清除
+    pub(crate) unsafe fn deallocate_unmarked(
+        &mut self,
+        ptr: *mut u8,
+        layout: Layout,
+    ) -> Result<(), SlabErr> {
+        self.deallocate_layout(ptr, layout, false)
+    }
+
+    unsafe fn deallocate_layout(
+        &mut self,
+        ptr: *mut u8,
+        layout: Layout,
+        marked: bool,
+    ) -> Result<(), SlabErr> {
+        let layout = self.align_layout(layout).map_err(|_| SlabErr::WrongSize)?;
+        if layout.size() == 0 {
+            return Ok(());
         }
-        //error!("in slab dealloc_fid not should run here")
+        match (self.fit_class(layout), self.owner(ptr)?) {
+            (Some(index), Owner::Slab { class, .. }) if index == class && marked => {
+                self.deallocate(index, ptr, layout.size())
+            }
+            (Some(index), Owner::Slab { class, .. }) if index == class => {
+                self.put_back(index, ptr, layout.size())
+            }
+            (None, Owner::Pages { pages, .. }) if pages * PGSZ == layout.size() => {
+                self.deallocate_pages(ptr as usize, pages)
+            }
+            _ => Err(SlabErr::SizeMismatch),
+        }
+    }
+
+    // 对象所属的slab或页块，指针需要是对象或页块的起始地址
+    pub(crate) fn owner(&self, ptr: *mut u8) -> Result<Owner, SlabErr> {
+        let addr = ptr as usize;
+        match self.pages.owner(addr) {
+            Some(Owner::Slab { class, start })
+                if self.classes.obj_start(class, start, addr) == addr =>
+            {
+                Ok(Owner::Slab { class, start })
+            }
+            Some(Owner::Pages { start, pages }) if start == addr => {
+                Ok(Owner::Pages { start, pages })
+            }
+            // 缓存中的对象由所属的缓存检查
+            Some(Owner::Cache { index, start }) => Ok(Owner::Cache { index, start }),
+            _ => Err(SlabErr::InvalidFree),
+        }
+    }
+
+    // 不需要layout的释放，由描述符找到对象所属的类别或页块
+    // 描述符记录每页属于哪个slab或页块，按layout释放时也由描述符检查layout
+    pub unsafe fn free(&mut self, ptr: *mut u8) -> Result<(), SlabErr> {
+        match self.owner(ptr)? {
+            Owner::Slab { class, .. } => self.deallocate(class, ptr, self.classes.size(class)),
+            Owner::Pages { pages, .. } => self.deallocate_pages(ptr as usize, pages),
+            Owner::Cache { index, .. } => self.caches[index].deallocate(&self.pages, ptr),
+        }
+    }
+
+    // 尝试原地将内存调整为new_layout，成功时不需要复制
+    // 新旧大小属于同一类别时直接成功，页内存则尝试与伙伴合并或释放伙伴
+    pub unsafe fn realloc_in_place(
+        &mut self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_layout: Layout,
+    ) -> bool {
+        match (self.fit_class(layout), self.fit_class(new_layout)) {
+            (Some(old), Some(new)) => old == new,
+            (None, None) if layout.size() != 0 && new_layout.size() != 0 => {
+                let (old, new) = match (self.align_layout(layout), self.align_layout(new_layout)) {
+                    (Ok(old), Ok(new)) => (old, new),
+                    _ => return false,
+                };
+                let resized = is_align!(ptr as usize, new.align())
+                    && self
+                        .buddy
+                        .resize(ptr as usize, old.size(), new.size())
+                        .is_ok();
+                if resized {
+                    self.set_pages(ptr as usize, new.size() / PGSZ);
+                }
+                resized
+            }
+            _ => false,
+        }
+    }
+
+    // 创建命名缓存，用于分配固定大小的对象，最多 MAX_CACHES 个
+    // ctor在每个slab页建立时对其中的对象调用
+    pub fn create_cache(
+        &mut self,
+        name: &'static str,
+        size: usize,
+        align: usize,
+        ctor: Option<Ctor>,
+    ) -> Result<CacheId, SlabErr> {
+        let index = self
+            .caches
+            .iter()
+            .position(|cache| !cache.is_active())
+            .ok_or(SlabErr::CacheFull)?;
+
+        let mut cache = ObjCache::create(name, size, align, ctor, PGSZ)?;
+        let gen = self.caches[index].gen.wrapping_add(1);
+        cache.gen = gen;
+        cache.index = index;
+        #[cfg(feature = "hardened")]
+        cache.set_key(self.key(&self.caches[index] as *const _ as usize ^ gen));
+        self.caches[index] = cache;
+        trace_event!(Event::CacheCreate { name, index });
+        Ok(CacheId { index, gen })
+    }
+
+    // 根据名字找到命名缓存
+    pub fn find_cache(&self, name: &str) -> Option<CacheId> {
+        self.caches
+            .iter()
+            .position(|cache| cache.name == Some(name))
+            .map(|index| CacheId {
+                index,
+                gen: self.caches[index].gen,
+            })
+    }
+
+    // 编号对应的缓存，缓存已被销毁或槽已被新的缓存使用时返回 NotFound
+    fn cache_mut(caches: &mut [ObjCache], id: CacheId) -> Result<&mut ObjCache, SlabErr> {
+        match caches.get_mut(id.index) {
+            Some(cache) if cache.is_active() && cache.gen == id.gen => Ok(cache),
+            _ => Err(SlabErr::NotFound),
+        }
+    }
+
+    pub unsafe fn cache_alloc(&mut self, id: CacheId) -> Result<*mut u8, SlabErr> {
+        match Self::cache_mut(&mut self.caches, id)?.allocate(&mut self.buddy, &self.pages) {
+            Err(SlabErr::Buddy(_)) if self.shrink() > 0 => {
+                Self::cache_mut(&mut self.caches, id)?.allocate(&mut self.buddy, &self.pages)
+            }
+            result => result,
+        }
+    }
+
+    pub unsafe fn cache_free(&mut self, id: CacheId, ptr: *mut u8) -> Result<(), SlabErr> {
+        Self::cache_mut(&mut self.caches, id)?.deallocate(&self.pages, ptr)
+    }
+
+    // 销毁命名缓存并归还其所有slab页，缓存中的对象需要已全部释放
+    pub unsafe fn destroy_cache(&mut self, id: CacheId) -> Result<(), SlabErr> {
+        Self::cache_mut(&mut self.caches, id)?.destroy(&mut self.buddy, &self.pages)
     }
 }
@@ -1,29 +1,880 @@
-use super::slab_allocator::SlabAllocator;
-use core::alloc::{GlobalAlloc, Layout};
-use spin::Mutex;
+#[cfg(feature = "debug")]
+use super::debug::{self, Quarantine};
+#[cfg(feature = "lock_free")]
+use super::magazine::FreeStacks;
+use super::{
+    cache::{CacheId, Ctor},
+    class::SizeClasses,
+    def::{Shrinker, SlabErr, ViolationHandler, MAX_CLASSES, MAX_CPUS, MAX_SHRINKERS},
+    magazine::CpuCache,
+    page::{Owner, PageTable},
+    slab_allocator::SlabAllocator,
+    stats::{ClassCounters, SlabStats},
+};
+use crate::buddy::def::MemPtr;
+use crate::{
+    def::dangling,
+    lock::{Lock, LockGuard, RawLock, SpinLock},
+};
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    hint::spin_loop,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    ptr::{copy_nonoverlapping, null_mut, NonNull},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use spin::Once;
 
-pub struct LockedSlab(Mutex<SlabAllocator>);
+/// 加锁的小内存分配器
+/// L 为保护内存池的锁，默认为自旋锁，需要在中断中分配时可以使用 IrqLock
+/// 小对象优先从每CPU的magazine或无锁栈分配和释放，只在需要时批量访问共享的内存池
+pub struct LockedSlab<
+    const PGSZ: usize = { crate::def::PGSZ },
+    const MAX_ORDER: usize = { crate::def::MAX_ORDER },
+    L: RawLock = SpinLock,
+> {
+    slab: Lock<L, SlabAllocator<PGSZ, MAX_ORDER>>,
+    classes: SizeClasses,         // 与slab中的相同，查找类别时不需要加锁
+    pages: Once<PageTable<PGSZ>>, // 与slab中的相同，查找对象所属的类别时不需要加锁
+    cpus: [Lock<L, CpuCache>; MAX_CPUS],
+    cpu_hook: Once<fn() -> usize>,
+    owner: AtomicUsize,    // 持有内存池锁的CPU编号+1，0表示没有CPU持有
+    shrinking: AtomicBool, // 正在回收，防止收缩回调中递归回收
+    counters: [ClassCounters; MAX_CLASSES],
+    shrinkers: Lock<L, [Option<Shrinker>; MAX_SHRINKERS]>,
+    violation: Once<ViolationHandler>,
+    #[cfg(feature = "debug")]
+    quarantine: Lock<L, Quarantine>,
+    #[cfg(feature = "lock_free")]
+    stacks: FreeStacks,
+}
+
+/// 内存池的锁，释放锁之前清除持有者
+struct SlabGuard<'a, const PGSZ: usize, const MAX_ORDER: usize, L: RawLock> {
+    guard: ManuallyDrop<LockGuard<'a, L, SlabAllocator<PGSZ, MAX_ORDER>>>,
+    owner: &'a AtomicUsize,
+}
+
+impl<const PGSZ: usize, const MAX_ORDER: usize, L: RawLock> Deref
+    for SlabGuard<'_, PGSZ, MAX_ORDER, L>
+{
+    type Target = SlabAllocator<PGSZ, MAX_ORDER>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
 
-unsafe impl Send for LockedSlab {}
+impl<const PGSZ: usize, const MAX_ORDER: usize, L: RawLock> DerefMut
+    for SlabGuard<'_, PGSZ, MAX_ORDER, L>
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<const PGSZ: usize, const MAX_ORDER: usize, L: RawLock> Drop
+    for SlabGuard<'_, PGSZ, MAX_ORDER, L>
+{
+    fn drop(&mut self) {
+        // 先清除持有者再释放锁，IrqLock 释放锁时才打开中断，中间不会发生重入；
+        // 其他CPU在此期间占有持有者后等待锁被释放
+        self.owner.store(0, Ordering::Release);
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+    }
+}
+
+unsafe impl<const PGSZ: usize, const MAX_ORDER: usize, L: RawLock> Send
+    for LockedSlab<PGSZ, MAX_ORDER, L>
+{
+}
+
+impl<const PGSZ: usize, const MAX_ORDER: usize, L: RawLock> LockedSlab<PGSZ, MAX_ORDER, L> {
+    // 仅用于初始化每CPU缓存数组
+    #[allow(clippy::declare_interior_mutable_const)]
+    const CPU_CACHE: Lock<L, CpuCache> = Lock::new(CpuCache::new());
+    #[allow(clippy::declare_interior_mutable_const)]
+    const COUNTERS: ClassCounters = ClassCounters::new();
 
-impl LockedSlab {
     pub const fn new_uninit() -> Self {
-        LockedSlab(Mutex::new(SlabAllocator::new()))
+        Self::from_slab(SlabAllocator::new())
+    }
+
+    // 使用指定的大小类别，例如 FINE_CLASSES
+    pub const fn with_classes(classes: &[usize]) -> Self {
+        Self::from_slab(SlabAllocator::with_classes(classes))
+    }
+
+    const fn from_slab(slab: SlabAllocator<PGSZ, MAX_ORDER>) -> Self {
+        LockedSlab {
+            classes: slab.classes,
+            pages: Once::new(),
+            slab: Lock::new(slab),
+            cpus: [Self::CPU_CACHE; MAX_CPUS],
+            cpu_hook: Once::new(),
+            owner: AtomicUsize::new(0),
+            shrinking: AtomicBool::new(false),
+            counters: [Self::COUNTERS; MAX_CLASSES],
+            shrinkers: Lock::new([None; MAX_SHRINKERS]),
+            violation: Once::new(),
+            #[cfg(feature = "debug")]
+            quarantine: Lock::new(Quarantine::new()),
+            #[cfg(feature = "lock_free")]
+            stacks: FreeStacks::new(),
+        }
     }
+
     pub fn init(&self, bottom: usize, top: usize) {
-        unsafe { self.0.lock().init(bottom, top) };
+        let mut slab = self.slab_guard();
+        unsafe { slab.init(bottom, top) };
+        self.pages.call_once(|| slab.pages);
+        #[cfg(feature = "lock_free")]
+        self.stacks.set_base(slab.buddy.zone_start());
+        #[cfg(all(feature = "lock_free", feature = "hardened"))]
+        self.stacks.set_key(Self::stack_key(slab.secret()));
+    }
+
+    // 无锁栈使用由内存池密钥派生的密钥
+    #[cfg(all(feature = "lock_free", feature = "hardened"))]
+    fn stack_key(secret: usize) -> usize {
+        crate::rand::mix(secret as u64) as usize
+    }
+
+    // 设置打乱分配顺序的随机数种子，应在初始化后尽快使用随机数设置
+    #[cfg(feature = "randomize")]
+    pub fn set_seed(&self, seed: u64) {
+        self.slab_guard().set_seed(seed)
+    }
+
+    // 设置编码空闲链表的密钥，初始化时由地址生成确定的默认密钥，
+    // 有随机数来源时应在第一次分配前设置
+    #[cfg(feature = "hardened")]
+    pub fn set_secret(&self, secret: usize) {
+        let mut slab = self.slab_guard();
+        slab.set_secret(secret);
+        // 无锁栈中的对象按旧密钥编码，先全部归还
+        #[cfg(feature = "lock_free")]
+        {
+            unsafe {
+                self.stacks
+                    .drain(&mut slab, |class, addr| self.is_object(class, addr))
+            };
+            self.stacks.set_key(Self::stack_key(secret));
+        }
+    }
+
+    // 设置每个内存池最多保留的空slab数，多余的空slab归还给页内存分配器
+    pub fn set_empty_limit(&self, limit: usize) {
+        self.slab_guard().set_empty_limit(limit)
+    }
+
+    // 页内存分配器中剩余的空闲页数
+    pub fn free_pages(&self) -> usize {
+        self.slab_guard().buddy.free_pages()
+    }
+
+    // 设置获取当前CPU编号的钩子，只能设置一次
+    // 设置后小对象优先从每CPU的magazine分配和释放，只有magazine空或满时才批量访问内存池
+    // 编号不小于 MAX_CPUS 的CPU不使用每CPU缓存
+    pub fn set_cpu_hook(&self, hook: fn() -> usize) {
+        self.cpu_hook.call_once(|| hook);
+    }
+
+    // 将cpu缓存的对象全部归还给共享的内存池，例如CPU下线时
+    pub fn drain_cpu(&self, cpu: usize) {
+        if let Some(cache) = self.cpus.get(cpu) {
+            let mut cache = cache.lock();
+            if let Ok(mut slab) = self.lock_slab(true) {
+                unsafe { cache.drain(&mut slab) };
+            }
+        }
+    }
+
+    // 设置违规处理函数，只能设置一次
+    // 未设置时 GlobalAlloc 和 Allocator 的释放忽略错误，对象不会被释放
+    pub fn set_violation_handler(&self, handler: ViolationHandler) {
+        self.violation.call_once(|| handler);
+    }
+
+    // 释放出错时调用违规处理函数，重入等暂时无法释放的情况不算违规
+    fn report(&self, err: SlabErr, ptr: *mut u8) {
+        let violation = matches!(
+            err,
+            SlabErr::DoubleFree | SlabErr::InvalidFree | SlabErr::SizeMismatch | SlabErr::Buddy(_)
+        );
+        if let (true, Some(handler)) = (violation, self.violation.get()) {
+            handler(err, ptr);
+        }
+    }
+
+    // 注册收缩回调，最多 MAX_SHRINKERS 个
+    // 回调在不持有内存池锁时调用，可以在其中释放内存
+    pub fn register_shrinker(&self, shrinker: Shrinker) -> Result<(), SlabErr> {
+        let mut shrinkers = self.shrinkers.lock();
+        let slot = shrinkers
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(SlabErr::ShrinkerFull)?;
+        *slot = Some(shrinker);
+        Ok(())
+    }
+
+    // 回收缓存的内存，返回本次回收归还给页内存分配器的页数
+    // 先调用收缩回调，再将每CPU缓存和无锁栈中的对象归还给内存池，最后归还所有空slab
+    // 只统计持锁期间本次回收释放的页，不包含其他CPU同时释放的页
+    // 同一CPU重入或收缩回调中再次调用时不回收，正在被使用的每CPU缓存跳过
+    pub fn shrink(&self) -> usize {
+        if self.shrinking.swap(true, Ordering::Acquire) {
+            return 0;
+        }
+
+        let shrinkers = *self.shrinkers.lock();
+        for shrinker in shrinkers.iter().flatten() {
+            shrinker();
+        }
+
+        let mut pages = 0;
+        for cache in self.cpus.iter() {
+            if let Some(mut cache) = cache.try_lock() {
+                if let Ok(mut slab) = self.lock_slab(true) {
+                    let free = slab.buddy.free_pages();
+                    unsafe { cache.drain(&mut slab) };
+                    pages += slab.buddy.free_pages().saturating_sub(free);
+                }
+            }
+        }
+        #[cfg(feature = "lock_free")]
+        if let Ok(mut slab) = self.lock_slab(true) {
+            let free = slab.buddy.free_pages();
+            unsafe {
+                self.stacks
+                    .drain(&mut slab, |class, addr| self.is_object(class, addr))
+            };
+            pages += slab.buddy.free_pages().saturating_sub(free);
+        }
+
+        if let Ok(mut slab) = self.lock_slab(true) {
+            pages += unsafe { slab.shrink() };
+        }
+
+        self.shrinking.store(false, Ordering::Release);
+        pages
+    }
+
+    // 当前CPU的编号，未设置钩子时为None
+    fn current_cpu(&self) -> Option<usize> {
+        self.cpu_hook.get().map(|hook| hook())
+    }
+
+    // 获取内存池的锁
+    // 设置了CPU钩子时，同一CPU重入(例如持有锁时发生中断并分配)返回 Reentrant，
+    // 未设置时无法区分重入和其他CPU的竞争，不做检测
+    // block为false时锁被占用则返回 WouldBlock
+    // 先占有持有者再获取锁，释放锁之前清除持有者，持有锁期间的重入都能被检测到；
+    // 设置钩子后各CPU按抢占持有者的顺序获得锁，不再保证 TicketLock 的公平性
+    fn lock_slab(&self, block: bool) -> Result<SlabGuard<'_, PGSZ, MAX_ORDER, L>, SlabErr> {
+        if let Some(cpu) = self.current_cpu() {
+            while let Err(owner) =
+                self.owner
+                    .compare_exchange(0, cpu + 1, Ordering::Acquire, Ordering::Relaxed)
+            {
+                if owner == cpu + 1 {
+                    return Err(SlabErr::Reentrant);
+                } else if !block {
+                    return Err(SlabErr::WouldBlock);
+                }
+                spin_loop();
+            }
+        }
+
+        let guard = if block {
+            self.slab.lock()
+        } else {
+            match self.slab.try_lock() {
+                Some(guard) => guard,
+                None => {
+                    self.owner.store(0, Ordering::Release);
+                    return Err(SlabErr::WouldBlock);
+                }
+            }
+        };
+
+        Ok(SlabGuard {
+            guard: ManuallyDrop::new(guard),
+            owner: &self.owner,
+        })
+    }
+
+    // 不能返回错误的接口获取内存池的锁，重入时panic而不是死锁
+    fn slab_guard(&self) -> SlabGuard<'_, PGSZ, MAX_ORDER, L> {
+        self.lock_slab(true)
+            .expect("slab allocator reentered while locked")
+    }
+
+    // 当前CPU的缓存
+    fn cpu_cache(&self) -> Option<&Lock<L, CpuCache>> {
+        self.cpus.get(self.current_cpu()?)
+    }
+
+    // 每CPU缓存只被本CPU使用，已被占用说明发生了重入，此时不使用缓存
+    unsafe fn cpu_alloc(&self, layout: Layout, block: bool) -> Option<*mut u8> {
+        let cache = self.cpu_cache()?;
+        let class = self.classes.index_layout(layout, PGSZ)?;
+        cache
+            .try_lock()?
+            .allocate(class, || self.lock_slab(block).ok())
+    }
+
+    unsafe fn cpu_free(&self, ptr: *mut u8, layout: Layout) -> bool {
+        match (self.cpu_cache(), self.classes.index_layout(layout, PGSZ)) {
+            (Some(cache), Some(class)) => match cache.try_lock() {
+                Some(mut cache) => cache.deallocate(class, ptr, || self.lock_slab(true).ok()),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    // 将无锁栈中的对象全部归还给共享的内存池
+    #[cfg(feature = "lock_free")]
+    pub fn drain_free_stacks(&self) {
+        if let Ok(mut slab) = self.lock_slab(true) {
+            unsafe {
+                self.stacks
+                    .drain(&mut slab, |class, addr| self.is_object(class, addr))
+            };
+        }
+    }
+
+    // 不经过共享内存池锁的快速路径
+    // 启用 lock_free 特性时，每个类别共享的无锁栈是小对象的空闲链表，分配和释放只在
+    // 栈空需要补充或积压过多需要归还时才访问内存池的锁
+    unsafe fn fast_alloc(&self, layout: Layout, block: bool) -> Option<*mut u8> {
+        if let Some(ptr) = self.cpu_alloc(layout, block) {
+            return Some(ptr);
+        }
+
+        #[cfg(feature = "lock_free")]
+        if let Some(class) = self.classes.index_layout(layout, PGSZ) {
+            return self.stacks.allocate(
+                class,
+                |class, addr| self.is_object(class, addr),
+                || self.lock_slab(block).ok(),
+            );
+        }
+
+        None
+    }
+
+    unsafe fn fast_free(&self, ptr: *mut u8, layout: Layout) -> bool {
+        if self.cpu_free(ptr, layout) {
+            return true;
+        }
+
+        #[cfg(feature = "lock_free")]
+        if let Some(class) = self.classes.index_layout(layout, PGSZ) {
+            self.stacks.deallocate(
+                class,
+                ptr,
+                |class, addr| self.is_object(class, addr),
+                || self.lock_slab(false).ok(),
+            );
+            return true;
+        }
+
+        false
+    }
+
+    // layout所属的类别，页内存分配器分配的内存没有类别
+    fn class_of(&self, layout: Layout) -> Option<usize> {
+        self.classes.index_layout(layout, PGSZ)
+    }
+
+    // 请求的布局所属类别的计数器，调试模式下对象实际位于容纳保护区的更大类别中，
+    // 统计仍按请求的布局计算
+    fn counters(&self, layout: Layout) -> Option<&ClassCounters> {
+        self.class_of(layout).map(|class| &self.counters[class])
+    }
+
+    // 类别中一个对象的布局
+    fn class_layout(&self, class: usize) -> Layout {
+        let size = self.classes.size(class);
+        unsafe { Layout::from_size_align_unchecked(size, self.classes.slab_align(class, PGSZ)) }
+    }
+
+    // 地址所属的slab或页块
+    fn owner(&self, addr: usize) -> Option<Owner> {
+        self.pages.get()?.owner(addr)
+    }
+
+    // 地址是第class个类别中一个对象的起始地址，用于检查无锁栈中的next
+    #[cfg(feature = "lock_free")]
+    fn is_object(&self, class: usize, addr: usize) -> bool {
+        matches!(self.owner(addr), Some(Owner::Slab { class: owner, start })
+            if owner == class && self.classes.obj_start(class, start, addr) == addr)
+    }
+
+    // 设置slab中对象的使用标记，返回原来的标记，不属于slab的块总是返回 !used
+    fn mark(&self, block: *mut u8, used: bool) -> bool {
+        let addr = block as usize;
+        match (self.pages.get(), self.owner(addr)) {
+            (Some(pages), Some(Owner::Slab { class, start })) => {
+                pages.mark(start, (addr - start) / self.classes.size(class), used)
+            }
+            _ => !used,
+        }
+    }
+
+    // 根据页描述符检查块与layout一致，避免对象被放入其他类别的每CPU缓存或空闲链表
+    // 页块的页数在释放时检查
+    fn check_layout(&self, block: *mut u8, layout: Layout) -> Result<(), SlabErr> {
+        let addr = block as usize;
+        let class = match self.owner(addr) {
+            Some(Owner::Slab { class, start })
+                if self.classes.obj_start(class, start, addr) == addr =>
+            {
+                Some(class)
+            }
+            Some(Owner::Pages { start, .. }) if start == addr => None,
+            _ => return Err(SlabErr::InvalidFree),
+        };
+        if self.class_of(layout) == class {
+            Ok(())
+        } else {
+            Err(SlabErr::SizeMismatch)
+        }
+    }
+
+    // 实际分配的布局，以及返回给使用者的地址相对分配地址的偏移
+    // 调试模式下属于某个类别的对象前后加上保护区
+    fn inner_layout(&self, layout: Layout) -> (Layout, usize) {
+        #[cfg(feature = "debug")]
+        if let Some((inner, offset)) = debug::redzone_layout(layout) {
+            if layout.size() != 0 && self.class_of(inner).is_some() {
+                return (inner, offset);
+            }
+        }
+        (layout, 0)
+    }
+
+    // 各个大小类别的统计信息
+    pub fn stats(&self) -> SlabStats {
+        let slab = self.slab_guard();
+        let mut stats = SlabStats::new();
+        for class in 0..self.classes.len() {
+            let size = self.classes.size(class);
+            stats.push(self.counters[class].snapshot(size, slab.pool_pages(class)));
+        }
+        stats
+    }
+
+    // 分配内存，block为false时不等待被占用的锁
+    // 内存不足时先通过 shrink 回收缓存的内存并调用注册的收缩回调，然后重试一次
+    #[cfg_attr(not(feature = "debug"), allow(unused_variables))]
+    unsafe fn allocate_inner(&self, layout: Layout, block: bool) -> Result<*mut u8, SlabErr> {
+        let (inner, offset) = self.inner_layout(layout);
+        let ptr = match self.fast_alloc(inner, block) {
+            Some(ptr) => ptr,
+            None => {
+                // 不等待锁时也不回收内存
+                let result = self.lock_slab(block)?.allocate_fit(inner);
+                match result {
+                    Err(_) if block && self.shrink() > 0 => self
+                        .lock_slab(block)?
+                        .allocate_fit(inner)
+                        .map_err(|_| SlabErr::NoMemory)?,
+                    result => result.map_err(|_| SlabErr::NoMemory)?,
+                }
+            }
+        };
+        if layout.size() != 0 {
+            if let Some(class) = self.class_of(inner) {
+                self.mark(ptr, true);
+                if let Some(counters) = self.counters(layout) {
+                    counters.alloc(layout.size());
+                }
+                #[cfg(feature = "debug")]
+                debug::arm(ptr, self.classes.size(class), offset, layout);
+            }
+        }
+        Ok(ptr.add(offset))
+    }
+
+    // 释放前根据页描述符检查layout，layout与分配时不一致的对象不会被释放
+    // 使用位图记录对象是否已分配给用户，重复释放的对象不会被再次放入空闲链表
+    #[cfg_attr(not(feature = "debug"), allow(unused_variables))]
+    unsafe fn deallocate_inner(&self, ptr: *mut u8, layout: Layout) -> Result<(), SlabErr> {
+        let (inner, offset) = self.inner_layout(layout);
+        let ptr = ptr.sub(offset);
+        if layout.size() != 0 {
+            self.check_layout(ptr, inner)?;
+            if let Some(class) = self.class_of(inner) {
+                if !self.mark(ptr, false) {
+                    return Err(SlabErr::DoubleFree);
+                }
+                if let Some(counters) = self.counters(layout) {
+                    counters.free(layout.size());
+                }
+                #[cfg(feature = "debug")]
+                return self.quarantine(ptr, class, offset, layout.size());
+            }
+        }
+        self.release(ptr, inner)
+    }
+
+    // 将块归还给每CPU缓存或内存池
+    unsafe fn release(&self, ptr: *mut u8, layout: Layout) -> Result<(), SlabErr> {
+        if self.fast_free(ptr, layout) {
+            return Ok(());
+        }
+        self.lock_slab(true)?.deallocate_unmarked(ptr, layout)
+    }
+
+    // 不需要layout的释放，由页描述符找到对象所属的类别、页块或命名缓存
+    // 不知道请求的大小，统计中按正在使用的对象的平均值扣除，之后类别的 fragment 只是估计；
+    // 调试模式下使用分配时保存的大小，统计仍然准确
+    /// # Safety
+    pub unsafe fn free(&self, ptr: *mut u8) -> Result<(), SlabErr> {
+        let (class, start) = match self.owner(ptr as usize) {
+            Some(Owner::Slab { class, start }) => (class, start),
+            Some(Owner::Pages { start, .. }) if start == ptr as usize => {
+                return self.lock_slab(true)?.free(ptr);
+            }
+            Some(Owner::Cache { .. }) => return self.lock_slab(true)?.free(ptr),
+            _ => return Err(SlabErr::InvalidFree),
+        };
+
+        let block = self.classes.obj_start(class, start, ptr as usize);
+        let offset = ptr as usize - block;
+        #[cfg(feature = "debug")]
+        {
+            if !self.mark(block as *mut u8, false) {
+                return Err(SlabErr::DoubleFree);
+            }
+            // 偏移与保存的布局不一致时不是分配时返回的地址，恢复使用标记
+            let layout =
+                match debug::stored_layout(block as *mut u8, self.classes.size(class), offset) {
+                    Some(layout) => layout,
+                    None => {
+                        self.mark(block as *mut u8, true);
+                        return Err(SlabErr::InvalidFree);
+                    }
+                };
+            // 没有前保护区的对象就在自己的类别中
+            match self.counters(layout) {
+                Some(counters) if offset != 0 => counters.free(layout.size()),
+                _ => self.counters[class].free(layout.size()),
+            }
+            self.quarantine(block as *mut u8, class, offset, layout.size())
+        }
+        #[cfg(not(feature = "debug"))]
+        {
+            if offset != 0 {
+                return Err(SlabErr::InvalidFree);
+            } else if !self.mark(ptr, false) {
+                return Err(SlabErr::DoubleFree);
+            }
+            self.counters[class].free_unsized();
+            self.release(ptr, self.class_layout(class))
+        }
+    }
+
+    // 分配的内存实际可用的大小，不超过该大小时不需要重新分配
+    // 释放时layout的大小可以是请求的大小到该大小之间的任意值
+    // 调试模式下为请求的大小，之后的字节属于保护区
+    pub fn usable_size(&self, ptr: *mut u8) -> Option<usize> {
+        match self.owner(ptr as usize)? {
+            Owner::Slab { class, start } => {
+                let block = self.classes.obj_start(class, start, ptr as usize);
+                let offset = ptr as usize - block;
+                #[cfg(feature = "debug")]
+                return unsafe {
+                    debug::stored_layout(block as *mut u8, self.classes.size(class), offset)
+                }
+                .map(|layout| layout.size());
+                #[cfg(not(feature = "debug"))]
+                (offset == 0).then(|| self.classes.size(class))
+            }
+            Owner::Pages { start, pages } if start == ptr as usize => Some(pages * PGSZ),
+            _ => None,
+        }
+    }
+
+    // 分配内存，同时返回实际可用的大小，统计中按可用的大小计算，
+    // 因此释放时需要使用可用大小的layout
+    /// # Safety
+    pub unsafe fn alloc_sized(&self, layout: Layout) -> Result<(*mut u8, usize), SlabErr> {
+        let ptr = self.allocate_inner(layout, true)?;
+        if layout.size() == 0 {
+            return Ok((ptr, 0));
+        }
+        let size = self.usable_size(ptr).unwrap_or(layout.size());
+        if let Some(counters) = self.counters(layout) {
+            counters.resize(layout.size(), size);
+        }
+        Ok((ptr, size))
+    }
+
+    // 释放内存并返回检查的结果，layout与分配时不一致时不释放并返回 SizeMismatch
+    // 对象已经被释放过时返回 DoubleFree
+    /// # Safety
+    pub unsafe fn dealloc_checked(&self, ptr: *mut u8, layout: Layout) -> Result<(), SlabErr> {
+        self.deallocate_inner(ptr, layout)
+    }
+
+    // 检查保护区并毒化，然后放入隔离区，归还离开隔离区的对象
+    #[cfg(feature = "debug")]
+    unsafe fn quarantine(
+        &self,
+        ptr: *mut u8,
+        class: usize,
+        offset: usize,
+        size: usize,
+    ) -> Result<(), SlabErr> {
+        let class_size = self.classes.size(class);
+        debug::check(ptr, class_size, offset, size);
+        debug::poison(ptr, class_size);
+
+        // 隔离区被占用时(例如重入)不隔离
+        let obj = (ptr as usize, class, offset);
+        let evicted = match self.quarantine.try_lock() {
+            Some(mut quarantine) => quarantine.push(obj),
+            None => Some(obj),
+        };
+        match evicted {
+            Some(obj) => self.release_quarantined(obj),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(feature = "debug")]
+    unsafe fn release_quarantined(
+        &self,
+        (block, class, offset): (usize, usize, usize),
+    ) -> Result<(), SlabErr> {
+        debug::verify(block as *mut u8, self.classes.size(class), offset);
+        self.release(block as *mut u8, self.class_layout(class))
+    }
+
+    // 设置隔离区中最多停留的对象数，为0时关闭隔离，最多为 QUARANTINE_SIZE
+    #[cfg(feature = "debug")]
+    pub fn set_quarantine(&self, depth: usize) {
+        self.quarantine.lock().set_depth(depth);
+        loop {
+            let obj = self.quarantine.lock().excess();
+            match obj {
+                Some(obj) => {
+                    let _ = unsafe { self.release_quarantined(obj) };
+                }
+                None => break,
+            }
+        }
+    }
+
+    // 尝试分配内存，锁被占用或发生重入时返回错误而不是等待
+    /// # Safety
+    pub unsafe fn try_alloc(&self, layout: Layout) -> Result<*mut u8, SlabErr> {
+        self.allocate_inner(layout, false)
+    }
+
+    // 分配一个阶数为order、自然对齐的大页
+    /// # Safety
+    pub unsafe fn allocate_huge(&self, order: usize) -> Result<MemPtr, SlabErr> {
+        let result = self.lock_slab(true)?.buddy.allocate_huge(order);
+        match result {
+            Err(_) if self.shrink() > 0 => Ok(self.lock_slab(true)?.buddy.allocate_huge(order)?),
+            result => Ok(result?),
+        }
+    }
+
+    /// # Safety
+    pub unsafe fn deallocate_huge(&self, addr: MemPtr, order: usize) -> Result<(), SlabErr> {
+        Ok(self.lock_slab(true)?.buddy.deallocate_huge(addr, order)?)
+    }
+
+    // 当前可以分配的大页数
+    pub fn huge_pages_available(&self, order: usize) -> usize {
+        self.slab_guard().buddy.huge_pages_available(order)
+    }
+
+    // 预留counts个阶数为order的大页
+    pub fn reserve_huge(&self, order: usize, counts: usize) -> Result<usize, SlabErr> {
+        Ok(unsafe { self.lock_slab(true)?.buddy.reserve_huge(order, counts) }?)
+    }
+
+    // 创建命名缓存
+    pub fn create_cache(
+        &self,
+        name: &'static str,
+        size: usize,
+        align: usize,
+        ctor: Option<Ctor>,
+    ) -> Result<CacheId, SlabErr> {
+        self.lock_slab(true)?.create_cache(name, size, align, ctor)
+    }
+
+    // 同一CPU重入时返回None
+    pub fn find_cache(&self, name: &str) -> Option<CacheId> {
+        self.lock_slab(true).ok()?.find_cache(name)
+    }
+
+    /// # Safety
+    pub unsafe fn cache_alloc(&self, id: CacheId) -> Result<*mut u8, SlabErr> {
+        let result = self.lock_slab(true)?.cache_alloc(id);
+        match result {
+            Err(SlabErr::Buddy(_)) if self.shrink() > 0 => self.lock_slab(true)?.cache_alloc(id),
+            result => result,
+        }
+    }
+
+    /// # Safety
+    pub unsafe fn cache_free(&self, id: CacheId, ptr: *mut u8) -> Result<(), SlabErr> {
+        self.lock_slab(true)?.cache_free(id, ptr)
+    }
+
+    /// # Safety
+    pub unsafe fn destroy_cache(&self, id: CacheId) -> Result<(), SlabErr> {
+        self.lock_slab(true)?.destroy_cache(id)
+    }
+
+    // 调整内存大小，无法原地调整时分配新内存并复制
+    unsafe fn reallocate(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_layout: Layout,
+    ) -> Result<*mut u8, SlabErr> {
+        // 带保护区的对象总是移动，使保护区随新大小重新设置
+        let (_, offset) = self.inner_layout(layout);
+        let (_, new_offset) = self.inner_layout(new_layout);
+        if offset == 0
+            && new_offset == 0
+            && self
+                .lock_slab(true)?
+                .realloc_in_place(ptr, layout, new_layout)
+        {
+            if let Some(counters) = self.counters(layout) {
+                counters.resize(layout.size(), new_layout.size());
+            }
+            return Ok(ptr);
+        }
+
+        let new_ptr = self.allocate_inner(new_layout, true)?;
+        copy_nonoverlapping(
+            ptr,
+            new_ptr,
+            core::cmp::min(layout.size(), new_layout.size()),
+        );
+        // 新内存已经分配，旧内存释放失败时只报告
+        if let Err(err) = self.deallocate_inner(ptr, layout) {
+            self.report(err, ptr);
+        }
+        Ok(new_ptr)
+    }
+
+    // Allocator 的 grow 和 shrink，处理零大小的内存
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            return (&self).allocate(new_layout);
+        } else if new_layout.size() == 0 {
+            (&self).deallocate(ptr, layout);
+            return Ok(NonNull::slice_from_raw_parts(dangling(new_layout), 0));
+        }
+
+        let new_ptr = self
+            .reallocate(ptr.as_ptr(), layout, new_layout)
+            .map_err(|_| AllocError)?;
+        let new_ptr = NonNull::new(new_ptr).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(new_ptr, new_layout.size()))
     }
 
     // pub fn allocate_fit(&self, layout: Layout){
-    //     self.0.lock().allocate_fit(layout).unwrap()
+    //     self.slab.lock().allocate_fit(layout).unwrap()
     // }
 }
 
-unsafe impl GlobalAlloc for LockedSlab {
+unsafe impl<const PGSZ: usize, const MAX_ORDER: usize, L: RawLock> GlobalAlloc
+    for LockedSlab<PGSZ, MAX_ORDER, L>
+{
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.0.lock().allocate_fit(layout).expect("alloc err")
+        self.allocate_inner(layout, true).unwrap_or(null_mut())
     }
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        self.0.lock().deallocate_fit(ptr, layout)
+        // 同一CPU持有内存池的锁时重入(Reentrant)释放，例如中断处理中释放，magazine满时
+        // 无法获取内存池的锁，dealloc不能返回错误，只能泄漏该内存；
+        // 启用 lock_free 时小对象在 fast_free 中放入无锁栈，不会走到这里
+        if let Err(err) = self.deallocate_inner(ptr, layout) {
+            self.report(err, ptr);
+        }
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        self.reallocate(ptr, layout, new_layout)
+            .unwrap_or(null_mut())
+    }
+}
+
+unsafe impl<const PGSZ: usize, const MAX_ORDER: usize, L: RawLock> Allocator
+    for &LockedSlab<PGSZ, MAX_ORDER, L>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            return Ok(NonNull::slice_from_raw_parts(dangling(layout), 0));
+        }
+
+        let ptr = unsafe { self.allocate_inner(layout, true) }.map_err(|_| AllocError)?;
+        let ptr = NonNull::new(ptr).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.allocate(layout)?;
+        unsafe { (ptr.as_ptr() as *mut u8).write_bytes(0, ptr.len()) };
+        Ok(ptr)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            if let Err(err) = self.deallocate_inner(ptr.as_ptr(), layout) {
+                self.report(err, ptr.as_ptr());
+            }
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new_ptr = self.resize(ptr, old_layout, new_layout)?;
+        (new_ptr.as_ptr() as *mut u8)
+            .add(old_layout.size())
+            .write_bytes(0, new_layout.size() - old_layout.size());
+        Ok(new_ptr)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
     }
 }
@@ -0,0 +1,150 @@
+use super::def::MAX_CLASSES;
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// 一个大小类别的统计信息
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClassStats {
+    pub size: usize,     // 类别大小
+    pub allocs: usize,   // 累计分配次数
+    pub frees: usize,    // 累计释放次数
+    pub live: usize,     // 正在使用的对象数
+    pub peak: usize,     // 正在使用的对象数的最大值
+    pub pages: usize,    // 内存池持有的页数
+    pub fragment: usize, // 正在使用的对象因取整到类别大小而浪费的字节数，经过free释放后为估计
+}
+
+/// 所有大小类别的统计信息，按 /proc/slabinfo 的格式输出
+#[derive(Debug, Clone, Copy)]
+pub struct SlabStats {
+    classes: [ClassStats; MAX_CLASSES],
+    len: usize,
+}
+
+impl SlabStats {
+    pub(crate) fn new() -> Self {
+        Self {
+            classes: [ClassStats::default(); MAX_CLASSES],
+            len: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, stats: ClassStats) {
+        self.classes[self.len] = stats;
+        self.len += 1;
+    }
+
+    pub fn classes(&self) -> &[ClassStats] {
+        &self.classes[..self.len]
+    }
+
+    // 大小为size的类别
+    pub fn get(&self, size: usize) -> Option<&ClassStats> {
+        self.classes().iter().find(|stats| stats.size == size)
+    }
+}
+
+impl fmt::Display for SlabStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "# name        <size> <allocs> <frees> <live> <peak> <pages> <fragment>"
+        )?;
+        for stats in self.classes() {
+            writeln!(
+                f,
+                "size-{:<7} {:>6} {:>8} {:>7} {:>6} {:>6} {:>7} {:>10}",
+                stats.size,
+                stats.size,
+                stats.allocs,
+                stats.frees,
+                stats.live,
+                stats.peak,
+                stats.pages,
+                stats.fragment
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// 一个类别的计数器，分配和释放可能不经过内存池的锁，因此使用原子变量
+#[derive(Debug)]
+pub(crate) struct ClassCounters {
+    allocs: AtomicUsize,
+    frees: AtomicUsize,
+    live: AtomicUsize,
+    peak: AtomicUsize,
+    requested: AtomicUsize, // 正在使用的对象实际请求的字节数
+}
+
+impl ClassCounters {
+    pub const fn new() -> Self {
+        Self {
+            allocs: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
+            live: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            requested: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn alloc(&self, size: usize) {
+        self.allocs.fetch_add(1, Ordering::Relaxed);
+        self.requested.fetch_add(size, Ordering::Relaxed);
+        let live = self.live.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak.fetch_max(live, Ordering::Relaxed);
+    }
+
+    // size为分配时记录的请求大小，alloc_sized 记录的是可用大小
+    pub fn free(&self, size: usize) {
+        self.frees.fetch_add(1, Ordering::Relaxed);
+        self.sub_requested(size);
+        self.live.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    // 释放时不知道请求的大小，按正在使用的对象的平均值扣除
+    #[cfg_attr(feature = "debug", allow(unused))]
+    pub fn free_unsized(&self) {
+        let live = self.live.load(Ordering::Relaxed).max(1);
+        let _ = self
+            .requested
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |requested| {
+                Some(requested - requested / live)
+            });
+        self.frees.fetch_add(1, Ordering::Relaxed);
+        self.live.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    // 对象在类别内原地调整大小
+    pub fn resize(&self, old_size: usize, new_size: usize) {
+        self.requested.fetch_add(new_size, Ordering::Relaxed);
+        self.sub_requested(old_size);
+    }
+
+    // free_unsized 按平均值扣除后记录的字节数只是估计，释放时的layout也可能大于请求的大小，
+    // 扣除时不低于0
+    fn sub_requested(&self, size: usize) {
+        let _ = self
+            .requested
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |requested| {
+                Some(requested.saturating_sub(size))
+            });
+    }
+
+    pub fn snapshot(&self, size: usize, pages: usize) -> ClassStats {
+        let live = self.live.load(Ordering::Relaxed);
+        let requested = self.requested.load(Ordering::Relaxed);
+        ClassStats {
+            size,
+            allocs: self.allocs.load(Ordering::Relaxed),
+            frees: self.frees.load(Ordering::Relaxed),
+            live,
+            peak: self.peak.load(Ordering::Relaxed),
+            pages,
+            fragment: (live * size).saturating_sub(requested),
+        }
+    }
+}
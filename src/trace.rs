@@ -0,0 +1,172 @@
+#[cfg(feature = "trace")]
+use core::sync::atomic::{AtomicU8, Ordering};
+#[cfg(feature = "trace")]
+use spin::Once;
+
+/// 追踪事件的级别，数值越大越详细
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off,
+    Error, // 分配失败
+    Info,  // slab的建立和释放等低频事件
+    Trace, // 每次分配和释放
+}
+
+/// 分配器内部发生的事件
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    BuddyInit {
+        start: usize,
+        end: usize,
+        free_pages: usize,
+    },
+    PageAlloc {
+        addr: usize,
+        pages: usize,
+    },
+    PageFree {
+        addr: usize,
+        pages: usize,
+    },
+    PageResize {
+        addr: usize,
+        old_pages: usize,
+        new_pages: usize,
+    },
+    PageExhausted {
+        size: usize,
+        align: usize,
+    },
+    HugeAlloc {
+        addr: usize,
+        order: usize,
+    },
+    HugeFree {
+        addr: usize,
+        order: usize,
+    },
+    HugeReserve {
+        order: usize,
+        counts: usize,
+    },
+    SlabGrow {
+        class: usize,
+        start: usize,
+        end: usize,
+        objs: usize,
+    },
+    SlabRelease {
+        class: usize,
+        start: usize,
+    },
+    SlabExhausted {
+        class: usize,
+    },
+    ObjAlloc {
+        class: usize,
+        addr: usize,
+    },
+    ObjFree {
+        class: usize,
+        addr: usize,
+    },
+    CacheCreate {
+        name: &'static str,
+        index: usize,
+    },
+    CacheGrow {
+        name: &'static str,
+        start: usize,
+    },
+    Shrink {
+        pages: usize,
+    },
+}
+
+impl Event {
+    pub fn level(&self) -> Level {
+        match self {
+            Event::PageExhausted { .. } | Event::SlabExhausted { .. } => Level::Error,
+            Event::BuddyInit { .. }
+            | Event::HugeReserve { .. }
+            | Event::SlabGrow { .. }
+            | Event::SlabRelease { .. }
+            | Event::CacheCreate { .. }
+            | Event::CacheGrow { .. }
+            | Event::Shrink { .. } => Level::Info,
+            _ => Level::Trace,
+        }
+    }
+}
+
+#[cfg(feature = "trace")]
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+#[cfg(feature = "trace")]
+static HOOK: Once<fn(&Event)> = Once::new();
+
+// 只输出不高于level的事件
+#[cfg(feature = "trace")]
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+// 设置接收事件的钩子，只能设置一次，未设置时通过 xxos_log 输出
+#[cfg(feature = "trace")]
+pub fn set_hook(hook: fn(&Event)) {
+    HOOK.call_once(|| hook);
+}
+
+#[cfg(feature = "trace")]
+pub(crate) fn emit(event: Event) {
+    if event.level() as u8 > LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    match HOOK.get() {
+        Some(hook) => hook(&event),
+        None if event.level() == Level::Error => xxos_log::error!("{:x?}", event),
+        None => xxos_log::info!("{:x?}", event),
+    }
+}
+
+#[cfg(all(test, feature = "trace"))]
+mod tests {
+    extern crate std;
+    use super::{set_hook, set_level, Event, Level};
+    use crate::slab::slab_allocator::SlabAllocator;
+    use core::{
+        alloc::Layout,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    #[test]
+    fn event_test() {
+        static GROW: AtomicUsize = AtomicUsize::new(0);
+        static ALLOC: AtomicUsize = AtomicUsize::new(0);
+        fn hook(event: &Event) {
+            match event {
+                Event::SlabGrow { .. } => GROW.fetch_add(1, Ordering::Relaxed),
+                Event::ObjAlloc { .. } => ALLOC.fetch_add(1, Ordering::Relaxed),
+                _ => 0,
+            };
+        }
+
+        let mem = std::vec![0usize; 4096 * 10];
+        let bottom = mem.as_ptr() as usize;
+        let mut slab: SlabAllocator = SlabAllocator::new();
+        set_hook(hook);
+        set_level(Level::Trace);
+        unsafe {
+            slab.init(bottom, bottom + mem.len() * 8);
+            #[cfg(feature = "hardened")]
+            slab.set_secret(0x5eed_1234);
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let ptr = slab.allocate_fit(layout).unwrap();
+            slab.deallocate_fit(ptr, layout).unwrap();
+        }
+        set_level(Level::Info);
+
+        // 其他测试也可能产生事件
+        assert!(GROW.load(Ordering::Relaxed) >= 1);
+        assert!(ALLOC.load(Ordering::Relaxed) >= 1);
+    }
+}